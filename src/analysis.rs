@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::constants::*;
+use crate::disasm;
+
+/*
+* Static analysis of a ROM: follow every path from the entry point without
+* running it, to split the program into basic blocks and subroutines and tell
+* code from data. Jumps through Bnnn depend on a register, so they cannot be
+* followed and are reported as unresolved instead.
+*/
+
+/*
+* Where execution can go after one instruction.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Flow {
+    Next, // On to the following instruction
+    Jump(u16), // 1nnn
+    Skip, // The following instruction or the one after
+    Call(u16), // 2nnn, then on to the following instruction when it returns
+    Return, // 00EE
+    Indirect, // Bnnn, somewhere we cannot know
+    Invalid, // Not an instruction, or off the end of the ROM
+}
+
+/*
+* How one basic block leads to another.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Next(u16), // Falls through, or skips over nothing
+    Jump(u16),
+    Skip(u16), // Taken when the condition skips the next instruction
+    Call(u16), // To a subroutine's entry point
+    Return(u16), // Where a call continues after the subroutine returns
+}
+
+impl Edge {
+    pub fn target(&self) -> u16 {
+        match *self {
+            Edge::Next(address) | Edge::Jump(address) | Edge::Skip(address) | Edge::Call(address) | Edge::Return(address) => address,
+        }
+    }
+}
+
+/*
+* A run of instructions only ever entered at the top and left at the bottom.
+*/
+pub struct Block {
+    pub start: u16,
+    pub end: u16, // Just past the last instruction
+    pub edges: Vec<Edge>,
+    pub unresolved: bool, // Ends with a Bnnn jump that cannot be followed
+    pub invalid: bool, // Runs into something that is not an instruction
+}
+
+pub struct Analysis {
+    rom: Vec<u8>,
+    pub blocks: BTreeMap<u16, Block>, // By start address
+    pub subroutines: BTreeMap<u16, BTreeSet<u16>>, // Entry point to the blocks it runs, the program's entry point included
+    pub unresolved: Vec<u16>, // Addresses of Bnnn jumps
+    code: Vec<bool>, // Whether each byte of the ROM is part of a reachable instruction
+}
+
+impl Analysis {
+    /*
+    * Analyse a ROM loaded at ROM_START, starting from its first instruction.
+    * Only the part that fits in memory is loaded, so only that is analysed.
+    */
+    pub fn new(rom: &[u8]) -> Self {
+        let rom = &rom[..rom.len().min(MEMORY_SIZE - ROM_START as usize)];
+        let mut analysis = Analysis {
+            rom: rom.to_vec(),
+            blocks: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            unresolved: Vec::new(),
+            code: vec![false; rom.len()],
+        };
+
+        let (instructions, leaders, entries) = analysis.explore();
+        analysis.build_blocks(&instructions, &leaders);
+        for entry in entries {
+            let blocks = analysis.blocks_from(entry);
+            analysis.subroutines.insert(entry, blocks);
+        }
+        analysis
+    }
+
+    fn opcode(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(ROM_START)? as usize;
+        match (self.rom.get(offset), self.rom.get(offset + 1)) {
+            (Some(&high), Some(&low)) => Some((high as u16) << 8 | low as u16),
+            _ => None,
+        }
+    }
+
+    fn flow(&self, address: u16) -> Flow {
+        let opcode = match self.opcode(address) {
+            Some(opcode) => opcode,
+            None => return Flow::Invalid,
+        };
+        let nnn = opcode & 0x0FFF;
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Flow::Next,
+                0x00EE => Flow::Return,
+                _ => Flow::Invalid,
+            },
+            0x1000 => Flow::Jump(nnn),
+            0x2000 => Flow::Call(nnn),
+            0x3000 | 0x4000 => Flow::Skip,
+            0x5000 | 0x9000 if opcode & 0xF == 0 => Flow::Skip,
+            0xE000 if matches!(opcode & 0xFF, 0x9E | 0xA1) => Flow::Skip,
+            0xB000 => Flow::Indirect,
+            _ if disasm::disassemble(opcode).starts_with("DW") => Flow::Invalid,
+            _ => Flow::Next,
+        }
+    }
+
+    /*
+    * Every reachable instruction, the addresses that start a basic block, and
+    * the entry points of the program and its subroutines.
+    */
+    fn explore(&mut self) -> (BTreeSet<u16>, BTreeSet<u16>, BTreeSet<u16>) {
+        let mut instructions = BTreeSet::new();
+        let mut leaders = BTreeSet::from([ROM_START]);
+        let mut entries = BTreeSet::from([ROM_START]);
+        let mut pending = vec![ROM_START];
+
+        while let Some(address) = pending.pop() {
+            if !instructions.insert(address) {
+                continue;
+            }
+            let next = address.wrapping_add(2);
+            let successors = match self.flow(address) {
+                Flow::Next => vec![next],
+                Flow::Jump(target) => {
+                    leaders.insert(target);
+                    vec![target]
+                },
+                Flow::Skip => {
+                    leaders.extend([next, next + 2]);
+                    vec![next, next + 2]
+                },
+                Flow::Call(target) => {
+                    leaders.extend([target, next]);
+                    entries.insert(target);
+                    vec![target, next]
+                },
+                Flow::Indirect => {
+                    self.unresolved.push(address);
+                    vec![]
+                },
+                Flow::Return | Flow::Invalid => vec![],
+            };
+            pending.extend(successors);
+        }
+
+        for &address in &instructions {
+            if self.opcode(address).is_some() {
+                let offset = (address - ROM_START) as usize;
+                self.code[offset] = true;
+                self.code[offset + 1] = true;
+            }
+        }
+        self.unresolved.sort();
+        (instructions, leaders, entries)
+    }
+
+    fn build_blocks(&mut self, instructions: &BTreeSet<u16>, leaders: &BTreeSet<u16>) {
+        for &start in leaders.iter().filter(|address| instructions.contains(address)) {
+            let mut block = Block { start, end: start, edges: Vec::new(), unresolved: false, invalid: false };
+            let mut address = start;
+            loop {
+                let next = address.wrapping_add(2);
+                block.end = next;
+                match self.flow(address) {
+                    Flow::Next if leaders.contains(&next) => block.edges.push(Edge::Next(next)),
+                    Flow::Next => {
+                        address = next;
+                        continue;
+                    },
+                    Flow::Jump(target) => block.edges.push(Edge::Jump(target)),
+                    Flow::Skip => block.edges.extend([Edge::Next(next), Edge::Skip(next + 2)]),
+                    Flow::Call(target) => block.edges.extend([Edge::Call(target), Edge::Return(next)]),
+                    Flow::Return => {},
+                    Flow::Indirect => block.unresolved = true,
+                    Flow::Invalid => {
+                        block.invalid = true;
+                        block.end = address;
+                    },
+                }
+                break;
+            }
+            self.blocks.insert(start, block);
+        }
+    }
+
+    /*
+    * The blocks a subroutine runs itself, not counting the ones it calls.
+    */
+    fn blocks_from(&self, entry: u16) -> BTreeSet<u16> {
+        let mut blocks = BTreeSet::new();
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            if let Some(block) = self.blocks.get(&start) {
+                if blocks.insert(start) {
+                    pending.extend(block.edges.iter()
+                        .filter(|edge| !matches!(edge, Edge::Call(_)))
+                        .map(Edge::target));
+                }
+            }
+        }
+        blocks
+    }
+
+    /*
+    * The first subroutine a block belongs to, with the program's entry point
+    * counting as one.
+    */
+    fn owner(&self, start: u16) -> Option<u16> {
+        self.subroutines.iter().find(|(_, blocks)| blocks.contains(&start)).map(|(&entry, _)| entry)
+    }
+
+    /*
+    * Whether a reachable instruction covers an address.
+    */
+    pub fn is_code(&self, address: u16) -> bool {
+        address.checked_sub(ROM_START).and_then(|offset| self.code.get(offset as usize)) == Some(&true)
+    }
+
+    /*
+    * Runs of ROM bytes no reachable instruction covers, as start address and
+    * length.
+    */
+    pub fn data(&self) -> Vec<(u16, usize)> {
+        let mut runs: Vec<(u16, usize)> = Vec::new();
+        for (offset, _) in self.code.iter().enumerate().filter(|(_, &code)| !code) {
+            let address = ROM_START + offset as u16;
+            match runs.last_mut() {
+                Some((start, length)) if *start as usize + *length == address as usize => *length += 1,
+                _ => runs.push((address, 1)),
+            }
+        }
+        runs
+    }
+
+    /*
+    * A disassembly of the whole ROM with the blocks and subroutines labelled,
+    * and data shown as bytes instead of as nonsense instructions.
+    */
+    pub fn listing(&self) -> String {
+        let mut listing = format!("; {} bytes, {} blocks, {} subroutines, {} bytes of data, {} unresolved jumps\n",
+                                  self.rom.len(), self.blocks.len(), self.subroutines.len() - 1,
+                                  self.data().iter().map(|(_, length)| length).sum::<usize>(), self.unresolved.len());
+
+        let data: BTreeMap<u16, usize> = self.data().into_iter().collect();
+        let mut address = ROM_START;
+        let end = ROM_START as usize + self.rom.len();
+        while (address as usize) < end {
+            if let Some(&length) = data.get(&address) {
+                listing.push_str(&format!("\n; data, {} bytes\n", length));
+                let offset = (address - ROM_START) as usize;
+                for (row, bytes) in self.rom[offset..offset + length].chunks(8).enumerate() {
+                    let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    listing.push_str(&format!("{:03X}  DB {}\n", address as usize + row * 8, bytes.join(", ")));
+                }
+                address += length as u16;
+                continue;
+            }
+
+            if self.subroutines.contains_key(&address) {
+                listing.push_str(&format!("\n{}:\n", label(address)));
+            } else if self.blocks.contains_key(&address) {
+                listing.push_str(&format!("L{:03X}:\n", address));
+            }
+
+            let opcode = self.opcode(address).unwrap_or((self.rom[(address - ROM_START) as usize] as u16) << 8);
+            let mut line = format!("{:03X}  {:04X}  {}", address, opcode, disasm::disassemble(opcode));
+            if self.unresolved.contains(&address) {
+                line = format!("{:<28}; unresolved, the target depends on a register", line);
+            } else if let Flow::Call(target) = self.flow(address) {
+                line = format!("{:<28}; {}", line, label(target));
+            }
+            listing.push_str(&line);
+            listing.push('\n');
+            address += 2;
+        }
+        listing
+    }
+
+    /*
+    * The control-flow graph in Graphviz DOT, one box per basic block grouped
+    * by subroutine. Calls are dashed and unresolved jumps are red.
+    */
+    pub fn dot(&self) -> String {
+        let mut dot = "digraph rom {\n    node [shape=box fontname=\"monospace\"];\n".to_string();
+
+        for (&entry, blocks) in &self.subroutines {
+            dot.push_str(&format!("    subgraph cluster_{:03X} {{\n        label=\"{}\";\n", entry, label(entry)));
+            for &start in blocks.iter().filter(|&&start| self.owner(start) == Some(entry)) {
+                let block = &self.blocks[&start];
+                let mut label = String::new();
+                let mut address = block.start;
+                while address < block.end {
+                    let opcode = self.opcode(address).unwrap_or(0);
+                    label.push_str(&format!("{:03X}  {}\\l", address, disasm::disassemble(opcode)));
+                    address += 2;
+                }
+                if block.invalid {
+                    label.push_str("not an instruction\\l");
+                }
+                let color = if block.unresolved || block.invalid { " color=red" } else { "" };
+                dot.push_str(&format!("        b{:03X} [label=\"{}\"{}];\n", start, label, color));
+            }
+            dot.push_str("    }\n");
+        }
+
+        for block in self.blocks.values() {
+            for edge in &block.edges {
+                let style = match edge {
+                    Edge::Next(_) | Edge::Jump(_) => "",
+                    Edge::Skip(_) => " [label=\"skip\"]",
+                    Edge::Call(_) => " [style=dashed label=\"call\"]",
+                    Edge::Return(_) => " [style=dotted label=\"return\"]",
+                };
+                if self.blocks.contains_key(&edge.target()) {
+                    dot.push_str(&format!("    b{:03X} -> b{:03X}{};\n", block.start, edge.target(), style));
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/*
+* What to call a subroutine, "main" for the program's entry point.
+*/
+pub fn label(entry: u16) -> String {
+    if entry == ROM_START { "main".to_string() } else { format!("sub_{:03X}", entry) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_calls_and_skips() {
+        // main: call sub_208, then skip either a jump to itself or a return;
+        // sub_208: return
+        let analysis = Analysis::new(&[0x22, 0x08, 0x30, 0x00, 0x12, 0x04, 0x00, 0xEE, 0x00, 0xEE]);
+        assert_eq!(analysis.subroutines.keys().copied().collect::<Vec<u16>>(), [0x200, 0x208]);
+        assert!(analysis.is_code(0x206));
+        assert_eq!(analysis.data(), []);
+    }
+
+    #[test]
+    fn roms_larger_than_memory_are_cut_off() {
+        // Runs straight through to the end of memory, with more than u16
+        // addresses can reach past it
+        let rom = vec![0x60; 0x10000];
+        let analysis = Analysis::new(&rom);
+        assert!(analysis.is_code(0xFFE));
+        assert!(!analysis.is_code(0x1000));
+        assert!(analysis.listing().lines().last().unwrap().starts_with("FFE"));
+    }
+}
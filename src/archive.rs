@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// Separates the archive from the file inside it, e.g. "pack.zip:games/pong.ch8".
+const SEPARATOR: char = ':';
+
+/*
+* Split a path to a file inside a zip archive into the archive and the inner
+* name. The archive ends at the first ".zip:", so the inner name may hold
+* ".zip" itself. The inner name is None for a bare archive. Paths that are not
+* in an archive give None.
+*/
+pub fn split(path: &Path) -> Option<(PathBuf, Option<String>)> {
+    let text = path.to_string_lossy();
+    let lower = text.to_ascii_lowercase();
+    let end = match lower.find(".zip:") {
+        Some(start) => start + ".zip".len(),
+        None if lower.ends_with(".zip") => text.len(),
+        None => return None,
+    };
+    let (archive, rest) = text.split_at(end);
+    match rest.strip_prefix(SEPARATOR) {
+        Some(inner) if !inner.is_empty() => Some((PathBuf::from(archive), Some(inner.to_string()))),
+        _ if rest.is_empty() => Some((PathBuf::from(archive), None)),
+        _ => None,
+    }
+}
+
+/*
+* The path to a file inside an archive.
+*/
+pub fn join(archive: &Path, inner: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.display(), SEPARATOR, inner))
+}
+
+/*
+* The file on disk that holds a ROM, the archive for a ROM inside one.
+*/
+pub fn file_of(path: &Path) -> PathBuf {
+    match split(path) {
+        Some((archive, _)) => archive,
+        None => path.to_path_buf(),
+    }
+}
+
+fn open(archive: &Path) -> Result<zip::ZipArchive<File>, String> {
+    let file = File::open(archive).map_err(|error| format!("Cannot open {}: {}", archive.display(), error))?;
+    zip::ZipArchive::new(file).map_err(|error| format!("Cannot read {}: {}", archive.display(), error))
+}
+
+/*
+* The names of the files in an archive that is_rom accepts, sorted.
+*/
+pub fn list(archive: &Path, is_rom: impl Fn(&Path) -> bool) -> Result<Vec<String>, String> {
+    let archive = open(archive)?;
+    let mut names: Vec<String> = archive.file_names()
+        .filter(|name| !name.ends_with('/') && is_rom(Path::new(name)))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/*
+* Read a file from an archive, returning its name and contents. Without an
+* inner name the archive must hold exactly one file accepted by is_rom.
+*/
+pub fn read(archive: &Path, inner: Option<&str>, is_rom: impl Fn(&Path) -> bool) -> Result<(String, Vec<u8>), String> {
+    let name = match inner {
+        Some(inner) => inner.to_string(),
+        None => {
+            let mut roms = list(archive, is_rom)?;
+            match roms.len() {
+                1 => roms.remove(0),
+                0 => return Err(format!("{} contains no ROMs", archive.display())),
+                _ => return Err(format!("{} contains several ROMs, choose one of {} as {}{}NAME",
+                                        archive.display(), roms.join(", "), archive.display(), SEPARATOR)),
+            }
+        },
+    };
+
+    let mut zip = open(archive)?;
+    let mut file = zip.by_name(&name).map_err(|error| format!("{}{}{}: {}", archive.display(), SEPARATOR, name, error))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(|error| format!("{}{}{}: {}", archive.display(), SEPARATOR, name, error))?;
+    Ok((name, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_str(path: &str) -> Option<(PathBuf, Option<String>)> {
+        split(Path::new(path))
+    }
+
+    #[test]
+    fn splits_on_the_first_zip() {
+        assert_eq!(split_str("pack.zip:games/old.zip.ch8"), Some((PathBuf::from("pack.zip"), Some("games/old.zip.ch8".to_string()))));
+        assert_eq!(split_str("pack.zip:inner.zip"), Some((PathBuf::from("pack.zip"), Some("inner.zip".to_string()))));
+        assert_eq!(split_str("Pack.ZIP"), Some((PathBuf::from("Pack.ZIP"), None)));
+    }
+
+    #[test]
+    fn other_paths_are_not_archives() {
+        assert_eq!(split_str("games/pong.ch8"), None);
+        assert_eq!(split_str("games.zip.d/pong.ch8"), None);
+        assert_eq!(split_str("pack.zip:"), None);
+    }
+}
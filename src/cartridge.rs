@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::archive;
+use crate::octo;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+
+// Files the emulator can play, by extension.
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "sc8", "xo8", "8o", "gif"];
+
+/*
+* Reading a program from disk, whatever form it comes in: a plain binary, Octo
+* source (.8o) or an Octo cartridge GIF, on its own or inside a zip archive.
+* Source is assembled, and a cartridge's embedded options are returned
+* alongside the binary.
+*/
+pub fn read(path: &Path) -> Result<(Vec<u8>, Option<OctoOptions>), String> {
+    match archive::split(path) {
+        Some((archive_path, inner)) => {
+            let (name, data) = archive::read(&archive_path, inner.as_deref(), is_rom)?;
+            program(data, &archive::join(&archive_path, &name))
+        },
+        None => {
+            let data = fs::read(path).map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+            program(data, path)
+        },
+    }
+}
+
+fn program(data: Vec<u8>, path: &Path) -> Result<(Vec<u8>, Option<OctoOptions>), String> {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+    if data.starts_with(b"GIF8") {
+        let cartridge = decode(&data).map_err(|error| format!("{} is not an Octo cartridge: {}", path.display(), error))?;
+        let program = octo::assemble(&cartridge.program).map_err(|error| format!("{}: {}", path.display(), error))?;
+        return Ok((program, Some(cartridge.options)));
+    }
+    if extension.as_deref() == Some("8o") {
+        let source = String::from_utf8_lossy(&data);
+        let program = octo::assemble(&source).map_err(|error| format!("{}: {}", path.display(), error))?;
+        return Ok((program, None));
+    }
+    Ok((data, None))
+}
+
+/*
+* Whether a file looks like something read can play, going by its extension.
+*/
+pub fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| ROM_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/*
+* What an Octo cartridge holds: the program's source and the options it was
+* saved with.
+*/
+#[derive(Deserialize)]
+struct Cartridge {
+    program: String,
+    #[serde(default)]
+    options: OctoOptions,
+}
+
+/*
+* The options Octo saves with a program, as named in its JSON.
+*/
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OctoOptions {
+    pub tickrate: Option<u32>,
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub fill_color2: Option<String>,
+    pub blend_color: Option<String>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub clip_quirks: Option<bool>,
+    pub jump_quirks: Option<bool>,
+    pub logic_quirks: Option<bool>,
+    pub v_blank_quirks: Option<bool>,
+}
+
+impl OctoOptions {
+    /*
+    * Octo's quirk flags over the given quirks.
+    */
+    pub fn quirks(&self, quirks: Quirks) -> Quirks {
+        let mut quirks = quirks;
+        quirks.shift = self.shift_quirks.unwrap_or(quirks.shift);
+        if let Some(load_store) = self.load_store_quirks {
+            quirks.memory_leave_i_unchanged = load_store;
+            quirks.memory_increment_by_x = false;
+        }
+        quirks.wrap = self.clip_quirks.map_or(quirks.wrap, |clip| !clip);
+        quirks.jump = self.jump_quirks.unwrap_or(quirks.jump);
+        quirks.logic = self.logic_quirks.unwrap_or(quirks.logic);
+        quirks.vblank = self.v_blank_quirks.unwrap_or(quirks.vblank);
+        quirks
+    }
+
+    /*
+    * The four colours Octo draws the bitplanes with, if the cartridge has them.
+    */
+    pub fn palette(&self) -> Option<Palette> {
+        let colors = [&self.background_color, &self.fill_color, &self.fill_color2, &self.blend_color];
+        let colors: Option<Vec<&str>> = colors.iter().map(|color| color.as_deref()).collect();
+        Palette::parse(&colors?.join(",")).ok()
+    }
+}
+
+/*
+* Octo hides the payload in the low two bits of every pixel's colour index,
+* four pixels to a byte with the most significant bits first, across all the
+* frames in order. The payload is a 32 bit big endian length, then that many
+* bytes of UTF-8 JSON.
+*/
+fn decode(data: &[u8]) -> Result<Cartridge, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(data).map_err(|error| error.to_string())?;
+
+    let mut pixels = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|error| error.to_string())? {
+        pixels.extend_from_slice(&frame.buffer);
+    }
+
+    let bytes: Vec<u8> = pixels.chunks_exact(4)
+        .map(|chunk| chunk.iter().fold(0, |byte, pixel| byte << 2 | (pixel & 3)))
+        .collect();
+    if bytes.len() < 4 {
+        return Err("no payload".to_string());
+    }
+    let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let json = bytes.get(4..4 + length).ok_or("payload is truncated")?;
+
+    serde_json::from_slice(json).map_err(|error| error.to_string())
+}
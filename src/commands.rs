@@ -0,0 +1,157 @@
+use std::fs;
+use std::path::Path;
+use crate::analysis::Analysis;
+use crate::cartridge;
+use crate::constants::*;
+use crate::cpu::Cpu;
+use crate::database::{self, Database, Profile};
+use crate::disasm;
+use crate::emulator;
+use crate::octo;
+use crate::options::Options;
+
+/*
+* The command line tools that work on a ROM without playing it. Each one
+* returns a message for the user when it fails.
+*/
+
+/*
+* Print a listing of the ROM as it would sit in memory, or write it to the
+* output file.
+*/
+pub fn disasm(options: &Options) -> Result<(), String> {
+    let (program, _) = cartridge::read(Path::new(rom_path(options)))?;
+    let mut memory = vec![0; ROM_START as usize];
+    memory.extend_from_slice(&program);
+
+    let listing: String = (ROM_START as usize..memory.len())
+        .step_by(2)
+        .map(|address| format!("{}\n", disasm::disassemble_at(&memory, address)))
+        .collect();
+    write_output(options, &listing)
+}
+
+/*
+* Assemble Octo source, writing the binary next to it with a .ch8 extension
+* unless told where.
+*/
+pub fn asm(options: &Options) -> Result<(), String> {
+    let source_path = Path::new(rom_path(options));
+    let source = fs::read_to_string(source_path).map_err(|error| format!("Cannot read {}: {}", source_path.display(), error))?;
+    let program = octo::assemble(&source).map_err(|error| format!("{}: {}", source_path.display(), error))?;
+
+    let output_path = match &options.output_path {
+        Some(path) => path.into(),
+        None => source_path.with_extension("ch8"),
+    };
+    fs::write(&output_path, &program).map_err(|error| format!("Cannot write {}: {}", output_path.display(), error))?;
+    println!("Wrote {} bytes to {}", program.len(), output_path.display());
+    Ok(())
+}
+
+/*
+* Print a listing of the ROM split into blocks and subroutines by following
+* its jumps and calls, or write it to the output file, and write the
+* control-flow graph as DOT if asked.
+*/
+pub fn analyze(options: &Options) -> Result<(), String> {
+    let (program, _) = cartridge::read(Path::new(rom_path(options)))?;
+    let analysis = Analysis::new(&program);
+
+    if let Some(path) = &options.dot_path {
+        fs::write(path, analysis.dot()).map_err(|error| format!("Cannot write {}: {}", path, error))?;
+    }
+    write_output(options, &analysis.listing())
+}
+
+/*
+* Print what is known about a ROM: its size and hash, and its ROM database
+* entry if it has one.
+*/
+pub fn info(options: &Options) -> Result<(), String> {
+    let (program, cartridge) = cartridge::read(Path::new(rom_path(options)))?;
+    let hash = database::sha1_hex(&program);
+
+    println!("File:       {}", rom_path(options));
+    println!("Size:       {} bytes", program.len());
+    println!("SHA1:       {}", hash);
+    if program.len() > MEMORY_SIZE - ROM_START as usize {
+        println!("Warning:    too large for {} bytes of memory", MEMORY_SIZE);
+    }
+    if cartridge.is_some() {
+        println!("Cartridge:  Octo, with its own options");
+    }
+
+    match Database::load().lookup(&hash) {
+        Some(rom) => {
+            println!("Title:      {}", rom.title);
+            if !rom.platforms.is_empty() {
+                println!("Platforms:  {}", rom.platforms.join(", "));
+            }
+            if let Some(tickrate) = rom.tickrate {
+                println!("Tick rate:  {}", tickrate);
+            }
+        },
+        None => println!("Title:      not in the ROM database"),
+    }
+    Ok(())
+}
+
+/*
+* Run a ROM without a window for the given number of frames and print the
+* screen it ends on, as test ROMs show their results there. With an expected
+* screen, Ok(false) means the screens differ.
+*/
+pub fn test(options: &Options) -> Result<bool, String> {
+    let mut cpu = Cpu::new();
+    emulator::prepare(&mut cpu, options);
+    let cartridge = cpu.load_rom(rom_path(options).to_string())?;
+    let profile = Profile::for_rom(options, &Database::load(), cpu.rom_hash(), cartridge.as_ref());
+    cpu.set_quirks(profile.quirks);
+
+    for _ in 0..options.test_frames {
+        emulator::run_frame(&mut cpu, profile.cycles_per_frame);
+    }
+
+    emulator::finish(&cpu, options, rom_path(options));
+    let screen = screen_text(&cpu.display);
+    print!("{}", screen);
+
+    match &options.expect_path {
+        Some(path) => {
+            let expected = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+            let matches = expected.lines().map(str::trim_end).eq(screen.lines());
+            println!("{}", if matches { "PASS" } else { "FAIL: the screen differs from the expected one" });
+            Ok(matches)
+        },
+        None => Ok(true),
+    }
+}
+
+/*
+* The display as text, a line per row with # for lit pixels and . for dark
+* ones.
+*/
+fn screen_text(display: &[u8]) -> String {
+    display.chunks(VIDEO_WIDTH as usize)
+        .map(|row| {
+            let mut line: String = row.iter().map(|&pixel| if pixel == 0 { '.' } else { '#' }).collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+fn rom_path(options: &Options) -> &str {
+    options.rom_path.as_deref().unwrap_or_default()
+}
+
+fn write_output(options: &Options, text: &str) -> Result<(), String> {
+    match &options.output_path {
+        Some(path) => fs::write(path, text).map_err(|error| format!("Cannot write {}: {}", path, error)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        },
+    }
+}
@@ -9,6 +9,11 @@ pub const REGISTER_COUNT: usize = 16;
 pub const STACK_LEVELS: usize = 16;
 pub const VIDEO_HEIGHT: u8 = 32;
 pub const VIDEO_WIDTH: u8 = 64;
+pub const FRAME_RATE: u32 = 60; // Timers and the display run at 60 Hz
+pub const CYCLES_PER_FRAME: u32 = 11; // Default instructions per frame, about 660 Hz
+pub const AUDIO_SAMPLE_RATE: u32 = 44100;
+pub const BEEP_FREQUENCY: u32 = 441;
+pub const BEEP_AMPLITUDE: i16 = 8000;
 
 pub const FONTSET: [u8; FONTSET_SIZE] = [
         0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
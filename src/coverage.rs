@@ -0,0 +1,111 @@
+use crate::analysis::Analysis;
+use crate::constants::*;
+use crate::disasm;
+
+/*
+* Which instructions a ROM executed, and how many times, over a session. The
+* report marks everything else that could run, so playtests show which paths
+* they never took. Static analysis tells code from data, and anything that
+* did run counts as code even where the analysis could not follow a jump to it.
+*/
+pub struct Coverage {
+    counts: Vec<u64>, // Times an instruction started at each address
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage { counts: vec![0; MEMORY_SIZE] }
+    }
+}
+
+impl Coverage {
+    pub fn record(&mut self, address: u16) {
+        self.counts[address as usize % MEMORY_SIZE] += 1;
+    }
+
+    /*
+    * The addresses to report on, in order, and whether each holds code: the
+    * ROM, with an instruction wherever the analysis found one or one ran and
+    * single bytes of data between them, and anything outside it that ran.
+    */
+    fn lines(&self, rom: &[u8]) -> Vec<(u16, bool)> {
+        let analysis = Analysis::new(rom);
+        let rom_end = ROM_START as usize + rom.len().min(MEMORY_SIZE - ROM_START as usize);
+        let ran = |address: usize| self.counts.get(address).is_some_and(|&count| count > 0);
+
+        let mut lines = Vec::new();
+        let mut address = 0;
+        while address < MEMORY_SIZE {
+            if !ran(address) && !(ROM_START as usize..rom_end).contains(&address) {
+                address += 1;
+                continue;
+            }
+            let code = ran(address) || analysis.is_code(address as u16);
+            lines.push((address as u16, code));
+            address += if code && !ran(address + 1) { 2 } else { 1 };
+        }
+        lines
+    }
+
+    /*
+    * The ROM's disassembly with how often each instruction ran, "-" for code
+    * that never did, and a summary at the top. Memory is taken as it is now,
+    * so code the program wrote itself shows as it last was.
+    */
+    pub fn listing(&self, rom: &[u8], memory: &[u8; MEMORY_SIZE]) -> String {
+        let lines = self.lines(rom);
+        let code: Vec<u16> = lines.iter().filter(|(_, code)| *code).map(|(address, _)| *address).collect();
+        let hit = code.iter().filter(|&&address| self.counts[address as usize] > 0).count();
+        let mut listing = format!("; {} of {} instructions executed ({}%)\n", hit, code.len(), (hit * 100).checked_div(code.len()).unwrap_or(0));
+
+        let mut data: Vec<(u16, u8)> = Vec::new();
+        for (address, code) in lines {
+            if !code {
+                data.push((address, memory[address as usize]));
+                if data.len() < 8 {
+                    continue;
+                }
+            }
+            listing.push_str(&data_line(&data));
+            data.clear();
+            if code {
+                let count = match self.counts[address as usize] {
+                    0 => "-".to_string(),
+                    count => count.to_string(),
+                };
+                listing.push_str(&format!("{:>10}  {}\n", count, disasm::disassemble_at(memory, address as usize)));
+            }
+        }
+        listing.push_str(&data_line(&data));
+        listing
+    }
+
+    /*
+    * The coverage in lcov's tracefile format, with the address of each
+    * instruction as its line number, for tools such as genhtml.
+    */
+    pub fn lcov(&self, rom: &[u8], rom_path: &str) -> String {
+        let code: Vec<u16> = self.lines(rom).into_iter().filter(|(_, code)| *code).map(|(address, _)| address).collect();
+        let mut lcov = format!("TN:\nSF:{}\n", rom_path);
+        for &address in &code {
+            lcov.push_str(&format!("DA:{},{}\n", address, self.counts[address as usize]));
+        }
+        let hit = code.iter().filter(|&&address| self.counts[address as usize] > 0).count();
+        lcov.push_str(&format!("LH:{}\nLF:{}\nend_of_record\n", hit, code.len()));
+        lcov
+    }
+}
+
+/*
+* Up to eight bytes of data as one line of the listing, nothing if there are
+* none.
+*/
+fn data_line(data: &[(u16, u8)]) -> String {
+    match data.first() {
+        Some(&(start, _)) => {
+            let bytes: Vec<String> = data.iter().map(|(_, byte)| format!("0x{:02X}", byte)).collect();
+            format!("{:>10}  {:03X}  DB {}\n", "", start, bytes.join(", "))
+        },
+        None => String::new(),
+    }
+}
@@ -1,10 +1,19 @@
 
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
-use std::fs::File;
-use std::io::prelude::*;
+use std::path::Path;
 use rand::prelude::*;
-use log::debug;
+use rand::rngs::StdRng;
+use log::{debug, info, log, trace, warn};
+use crate::cartridge::{self, OctoOptions};
 use crate::constants;
+use crate::database;
+use crate::disasm;
+use crate::logging;
+use crate::quirks::Quirks;
+use crate::coverage::Coverage;
+use crate::profiler::Profiler;
+use crate::strict::{Finding, Report};
 use crate::constants::*;
 
 pub struct Cpu {
@@ -21,6 +30,21 @@ pub struct Cpu {
     pub display: [u8; constants::VIDEO_WIDTH as usize * constants::VIDEO_HEIGHT as usize],
     opcode: u16,
     pub draw_flag: bool,
+    rng: StdRng,
+    cycles: u64, // Instructions executed so far
+    written_at: [u64; constants::MEMORY_SIZE], // Cycle each address was last written by the program, 0 if never
+    executed_at: [u64; constants::MEMORY_SIZE], // Cycle each address was last executed as part of an instruction, 0 if never
+    modified_code: [u64; constants::MEMORY_SIZE], // Cycle each address was last written after being executed, 0 if never
+    sprites_drawn: BTreeMap<u16, u8>, // Address and tallest height of every sprite drawn
+    rom: Vec<u8>, // The loaded ROM image, kept so a reset can restore it
+    rom_hash: String, // SHA1 of the ROM image, how the ROM database knows it
+    quirks: Quirks,
+    vblank: bool, // A frame has started since the last draw, for the vblank quirk
+    instruction: u16, // Address of the instruction being executed
+    warned_at: HashSet<(Finding, u16)>, // Findings already warned about, by instruction address
+    strict: Option<Report>, // Everything suspicious the program did, in strict mode
+    coverage: Option<Coverage>, // How often each instruction ran, when asked for
+    profiler: Option<Profiler>, // Where the time went by subroutine, when asked for
 }
 
 impl fmt::Display for Cpu {
@@ -59,14 +83,13 @@ impl Cpu {
         // Initialize the memory with 0s
         let mut memory = [0; constants::MEMORY_SIZE];
         // Load the fontset into memory
-        for i in 0..constants::FONTSET_SIZE {
-            memory[FONTSET_START_ADDRESS as usize + i] = FONTSET[i];
-        }
+        let font_start = FONTSET_START_ADDRESS as usize;
+        memory[font_start..font_start + FONTSET_SIZE].copy_from_slice(&FONTSET);
 
         // Initialize the CPU
         Cpu {
             registers: [0; constants::REGISTER_COUNT],
-            memory: [0; constants::MEMORY_SIZE],
+            memory,
             index: 0,
             pc: ROM_START, // Start of ROM in memory
             stack: [0; constants::STACK_LEVELS],
@@ -77,29 +100,268 @@ impl Cpu {
             display: [0; constants::VIDEO_WIDTH as usize * constants::VIDEO_HEIGHT as usize],
             opcode: 0,
             draw_flag: false,
+            rng: StdRng::from_entropy(),
+            cycles: 0,
+            written_at: [0; constants::MEMORY_SIZE],
+            executed_at: [0; constants::MEMORY_SIZE],
+            modified_code: [0; constants::MEMORY_SIZE],
+            sprites_drawn: BTreeMap::new(),
+            rom: Vec::new(),
+            rom_hash: String::new(),
+            quirks: Quirks::default(),
+            vblank: true,
+            instruction: ROM_START,
+            warned_at: HashSet::new(),
+            strict: None,
+            coverage: None,
+            profiler: None,
         }
     }
 
     /*
-    * Load a ROM into memory
+    * Restart the loaded ROM. A hard reset also restores memory to the font and
+    * the ROM image, a soft reset leaves memory as the program left it. Either
+    * starts a new frame and warns about findings again. The instruction count
+    * and sprite history carry on across resets.
     */
-    pub fn load_rom(&mut self, file_name: String) {
+    pub fn reset(&mut self, soft: bool) {
+        self.registers = [0; constants::REGISTER_COUNT];
+        self.index = 0;
+        self.pc = ROM_START;
+        self.stack = [0; constants::STACK_LEVELS];
+        self.sp = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.keyboard = [0; constants::KEY_COUNT];
+        self.display = [0; constants::VIDEO_WIDTH as usize * constants::VIDEO_HEIGHT as usize];
+        self.opcode = 0;
+        self.instruction = ROM_START;
+        self.draw_flag = true;
+        self.vblank = true;
+        self.warned_at.clear();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.restart();
+        }
 
-        let mut file = File::open(file_name).expect("ROM file not found");
-        let mut buffer: Vec<u8> = Vec::new();
+        if !soft {
+            self.memory = [0; constants::MEMORY_SIZE];
+            let font_start = FONTSET_START_ADDRESS as usize;
+            self.memory[font_start..font_start + FONTSET_SIZE].copy_from_slice(&FONTSET);
+            self.copy_rom();
+            self.written_at = [0; constants::MEMORY_SIZE];
+            self.executed_at = [0; constants::MEMORY_SIZE];
+            self.modified_code = [0; constants::MEMORY_SIZE];
+        }
+    }
+
+    /*
+    * Seed the random number generator so runs are reproducible
+    */
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /*
+    * Load a ROM into memory. Octo source and cartridges are assembled first,
+    * and a cartridge's embedded options are returned.
+    */
+    pub fn load_rom(&mut self, file_name: String) -> Result<Option<OctoOptions>, String> {
 
         // Read the file into a buffer
-        file.read_to_end(&mut buffer).expect("Error reading file");
+        let (buffer, cartridge) = cartridge::read(Path::new(&file_name))?;
+
+        self.rom_hash = database::sha1_hex(&buffer);
+        info!(target: logging::CPU, "Loaded {} bytes from {}, SHA1 {}", buffer.len(), file_name, self.rom_hash);
+        if buffer.len() > MEMORY_SIZE - ROM_START as usize {
+            warn!(target: logging::CPU, "The ROM is too large, only the first {} bytes fit in memory", MEMORY_SIZE - ROM_START as usize);
+        }
+        self.rom = buffer;
+        self.copy_rom();
+        Ok(cartridge)
+    }
+
+    /*
+    * Copy the ROM image into memory, anything past the end of memory is dropped
+    */
+    fn copy_rom(&mut self) {
+        let start = ROM_START as usize;
+        let length = self.rom.len().min(MEMORY_SIZE - start);
+        self.memory[start..start + length].copy_from_slice(&self.rom[..length]);
+    }
+
+    /*
+    * Check the program more closely as it runs, recording every suspicious
+    * thing it does in a report
+    */
+    pub fn enable_strict(&mut self) {
+        self.strict = Some(Report::default());
+    }
+
+    pub fn strict_report(&self) -> Option<&Report> {
+        self.strict.as_ref()
+    }
+
+    /*
+    * Count how often each instruction runs, across resets, for a coverage
+    * report
+    */
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /*
+    * Count the instructions and frames each subroutine runs, across resets,
+    * for a profile
+    */
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::default());
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    /*
+    * Emulate a particular interpreter's behaviour, see Quirks
+    */
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn registers(&self) -> &[u8; constants::REGISTER_COUNT] {
+        &self.registers
+    }
+
+    pub fn memory(&self) -> &[u8; constants::MEMORY_SIZE] {
+        &self.memory
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn stack(&self) -> &[u16; constants::STACK_LEVELS] {
+        &self.stack
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /*
+    * The cycle each address was last written by the program, 0 if never.
+    */
+    pub fn written_at(&self) -> &[u64; constants::MEMORY_SIZE] {
+        &self.written_at
+    }
+
+    /*
+    * The cycle each address was last executed, as either byte of an
+    * instruction, 0 if never.
+    */
+    pub fn executed_at(&self) -> &[u64; constants::MEMORY_SIZE] {
+        &self.executed_at
+    }
+
+    /*
+    * The cycle each address was last written after it had been executed, 0
+    * if never. Anything caching decoded instructions must drop them when
+    * their bytes show up here.
+    */
+    pub fn modified_code(&self) -> &[u64; constants::MEMORY_SIZE] {
+        &self.modified_code
+    }
+
+    /*
+    * Every address Dxyn has drawn a sprite from this session, with the
+    * tallest height drawn from it.
+    */
+    pub fn sprites_drawn(&self) -> &BTreeMap<u16, u8> {
+        &self.sprites_drawn
+    }
+
+    /*
+    * Store a byte on behalf of the program, remembering when it was written.
+    * Addresses past the end of memory wrap around to the start.
+    */
+    fn write_memory(&mut self, address: usize, value: u8) {
+        if address >= MEMORY_SIZE {
+            self.suspicious(Finding::MemoryPastEnd, format!("writes past the end of memory, to {:X}", address));
+        }
+        let address = address % MEMORY_SIZE;
+        self.memory[address] = value;
+        self.written_at[address] = self.cycles;
+
+        if self.executed_at[address] != 0 {
+            self.modified_code[address] = self.cycles;
+            self.suspicious(Finding::SelfModifyingCode, format!("writes {:02X} over code at {:03X}", value, address));
+        }
+    }
+
+    /*
+    * Load a byte on behalf of the program, wrapping around like write_memory
+    */
+    fn read_memory(&mut self, address: usize) -> u8 {
+        if address >= MEMORY_SIZE {
+            self.suspicious(Finding::MemoryPastEnd, format!("reads past the end of memory, from {:X}", address));
+        }
+        self.memory[address % MEMORY_SIZE]
+    }
 
-        // Load the fonts into CPU memory
-        for i in 0..FONTSET_SIZE {
-            self.memory[FONTSET_START_ADDRESS as usize + i] = FONTSET[i];
+    /*
+    * Warn about something a working ROM should not do, once per instruction
+    * so a loop doing it does not flood the log, and add it to the strict
+    * mode report
+    */
+    fn suspicious(&mut self, finding: Finding, message: String) {
+        if let Some(report) = self.strict.as_mut() {
+            report.record(finding, self.instruction, self.cycles, &message);
+        }
+        if self.warned_at.insert((finding, self.instruction)) {
+            log!(target: logging::CPU, finding.level(), "{:03X}: {}", self.instruction, message);
         }
+    }
+
+    fn not_an_instruction(&mut self) {
+        self.suspicious(Finding::InvalidInstruction,
+                        format!("{:04X} is not an instruction, the program may be executing data", self.opcode));
+    }
 
-        // Load the buffer into CPU memory
-        for i in 0..buffer.len() {
-            self.memory[ROM_START as usize + i] = buffer[i] as u8;
-            debug!("{}", self.memory[ROM_START as usize + i]);
+    /*
+    * Strict mode's checks on where the instruction about to run came from
+    */
+    fn check_fetch(&mut self) {
+        let pc = self.pc as usize;
+        let font = FONTSET_START_ADDRESS as usize..FONTSET_START_ADDRESS as usize + FONTSET_SIZE;
+        let rom = ROM_START as usize..ROM_START as usize + self.rom.len();
+
+        if pc % 2 == 1 {
+            self.suspicious(Finding::MisalignedPc, format!("PC is at an odd address, {:03X}", pc));
+        }
+        if font.contains(&pc) {
+            self.suspicious(Finding::ExecutedFont, "executing the font".to_string());
+        } else if !rom.contains(&pc) && pc < MEMORY_SIZE && self.written_at[pc] == 0 {
+            self.suspicious(Finding::ExecutedUnwritten, "executing memory outside the ROM that was never written".to_string());
         }
     }
 
@@ -107,8 +369,7 @@ impl Cpu {
     * Generate a random u8 number
     */
     fn rand_gen(&mut self) -> u8 {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(0..=255)
+        self.rng.gen_range(0..=255)
     }
 
     /*
@@ -127,7 +388,9 @@ impl Cpu {
     */
     fn op_00ee(&mut self) {
         if self.sp == 0 {
-            panic!("Stack underflow in op_00ee");
+            // Nothing to return to, carry on with the next instruction
+            self.suspicious(Finding::StackUnderflow, "returns with nothing on the stack".to_string());
+            return;
         }
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
@@ -139,6 +402,9 @@ impl Cpu {
     */
     fn op_1nnn(&mut self) {
         self.pc = self.opcode & 0x0FFF;
+        if self.strict.is_some() && self.pc == self.instruction {
+            self.suspicious(Finding::HaltLoop, "jumps to itself, the program has stopped".to_string());
+        }
     }
 
     /*
@@ -146,6 +412,12 @@ impl Cpu {
     *   Call subroutine at nnn.
     */
     fn op_2nnn(&mut self) {
+        if self.sp as usize >= constants::STACK_LEVELS {
+            // No room to return here, so skip the call
+            self.suspicious(Finding::StackOverflow,
+                            format!("calls {:03X} with all {} stack levels in use", self.opcode & 0x0FFF, constants::STACK_LEVELS));
+            return;
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
         self.pc = self.opcode & 0x0FFF;
@@ -229,7 +501,10 @@ impl Cpu {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
 
-        self.registers[vx] = self.registers[vx] | self.registers[vy];
+        self.registers[vx] |= self.registers[vy];
+        if self.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     /*
@@ -240,7 +515,10 @@ impl Cpu {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
 
-        self.registers[vx] = self.registers[vx] & self.registers[vy];
+        self.registers[vx] &= self.registers[vy];
+        if self.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     /*
@@ -251,7 +529,10 @@ impl Cpu {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
 
-        self.registers[vx] = self.registers[vx] ^ self.registers[vy];
+        self.registers[vx] ^= self.registers[vy];
+        if self.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     /*
@@ -264,13 +545,9 @@ impl Cpu {
 
         let (sum, overflow) = (self.registers[vx]).overflowing_add(self.registers[vy]);
 
-        if overflow {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
-
+        // The flag is written last, so it wins when Vx is VF
         self.registers[vx] = sum;
+        self.registers[0xF] = if overflow { 1 } else { 0 };
     }
 
     /*
@@ -281,25 +558,22 @@ impl Cpu {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
 
-        self.registers[0xF] = if self.registers[vx] > self.registers[vy] { 1 } else { 0 };
+        let not_borrow = if self.registers[vx] >= self.registers[vy] { 1 } else { 0 };
         self.registers[vx] = self.registers[vx].wrapping_sub(self.registers[vy]);
+        self.registers[0xF] = not_borrow;
     }
 
     /*
-    *   8xy6 - SHR Vx (shift right Vx by 1)
-    *   Set Vx = Vx SHR 1.
+    *   8xy6 - SHR Vx {, Vy} (shift right by 1)
+    *   Set Vx = Vx SHR 1, or Vy SHR 1 without the shift quirk, set VF = the bit shifted out.
     */
     fn op_8xy6(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
-        let lsb = self.registers[vx] >> 0x1;
-
-        if lsb == 1 {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        let value = if self.quirks.shift { self.registers[vx] } else { self.registers[vy] };
 
-        self.registers[vx] >>= 1;
+        self.registers[vx] = value >> 1;
+        self.registers[0xF] = value & 0x1;
     }
 
     /*
@@ -310,28 +584,22 @@ impl Cpu {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
 
-        let (sum, overflow) = (self.registers[vx]).overflowing_add(self.registers[vy]);
-
-        if overflow {
-            self.registers[0xF] = 0;
-        } else {
-            self.registers[0xF] = 1;
-        }
-
-        self.registers[vy] = sum;
-
+        let not_borrow = if self.registers[vy] >= self.registers[vx] { 1 } else { 0 };
+        self.registers[vx] = self.registers[vy].wrapping_sub(self.registers[vx]);
+        self.registers[0xF] = not_borrow;
     }
 
     /*
-    *   8xyE - SHL Vx {, Vy} (shift left Vx by 1)
-    *   Set Vx = Vx SHL 1.
+    *   8xyE - SHL Vx {, Vy} (shift left by 1)
+    *   Set Vx = Vx SHL 1, or Vy SHL 1 without the shift quirk, set VF = the bit shifted out.
     */
     fn op_8xye(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        let value = if self.quirks.shift { self.registers[vx] } else { self.registers[vy] };
 
-        self.registers[0xF] = (self.registers[vx] & 0x80) >> 7;
-        self.registers[vx] <<= 1;
-
+        self.registers[vx] = value << 1;
+        self.registers[0xF] = (value & 0x80) >> 7;
     }
 
     /*
@@ -357,10 +625,11 @@ impl Cpu {
 
     /*
     *   Bnnn - JP V0, addr
-    *   Jump to location nnn + V0.
+    *   Jump to location nnn + V0, or nnn + Vx with the jump quirk (Bxnn).
     */
     fn op_bnnn(&mut self) {
-        self.pc = (self.registers[0] as u16) + (self.opcode & 0x0FFF);
+        let register = if self.quirks.jump { ((self.opcode & 0x0F00) >> 8) as usize } else { 0 };
+        self.pc = (self.registers[register] as u16) + (self.opcode & 0x0FFF);
     }
 
     /*
@@ -377,23 +646,51 @@ impl Cpu {
     /*
     *   Dxyn - DRW Vx, Vy, nibble
     *   Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-    *   TODO maybe incorrect
+    *   Without the wrap quirk the sprite is clipped at the screen edges, with the
+    *   vblank quirk only one sprite is drawn per frame.
     */
     fn op_dxyn(&mut self) {
         let x = ((self.opcode & 0x0F00) >> 8) as usize;
         let y = ((self.opcode & 0x00F0) >> 4) as usize;
         let height = (self.opcode & 0x000F) as usize;
 
+        if self.quirks.vblank {
+            if !self.vblank {
+                // Try again until the next frame starts
+                self.pc -= 2;
+                return;
+            }
+            self.vblank = false;
+        }
+
         // Reset collision flag
         self.registers[0xF] = 0;
 
+        if self.index as usize + height > MEMORY_SIZE {
+            self.suspicious(Finding::SpritePastEnd,
+                            format!("draws a {} row sprite from {:03X}, past the end of memory", height, self.index));
+        }
+
+        let tallest = self.sprites_drawn.entry(self.index).or_insert(0);
+        *tallest = (*tallest).max(height as u8);
+
+        // The starting position always wraps
+        let start_x = self.registers[x] as usize % VIDEO_WIDTH as usize;
+        let start_y = self.registers[y] as usize % VIDEO_HEIGHT as usize;
+
         for row in 0..height {
-            let sprite_byte = self.memory[(self.index + row as u16) as usize];
-            let screen_y = (self.registers[y] as usize + row) % VIDEO_HEIGHT as usize;
+            let sprite_byte = self.memory[(self.index as usize + row) % MEMORY_SIZE];
+            if !self.quirks.wrap && start_y + row >= VIDEO_HEIGHT as usize {
+                break;
+            }
+            let screen_y = (start_y + row) % VIDEO_HEIGHT as usize;
             
             // Iterate over each pixel in the sprite byte
             for col in 0..8 {
-                let screen_x = (self.registers[x] as usize + col) % VIDEO_WIDTH as usize;
+                if !self.quirks.wrap && start_x + col >= VIDEO_WIDTH as usize {
+                    break;
+                }
+                let screen_x = (start_x + col) % VIDEO_WIDTH as usize;
                 let sprite_pixel = (sprite_byte >> (7 - col)) & 0x1;
 
                 // Check for collision
@@ -416,7 +713,7 @@ impl Cpu {
     */
     fn op_ex9e(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
-        let key = self.registers[vx] as usize;
+        let key = self.key_in(vx);
         let key_state = self.keyboard[key];
 
         if key_state != 0 {
@@ -433,7 +730,7 @@ impl Cpu {
     */
     fn op_exa1(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
-        let key = self.registers[vx] as usize;
+        let key = self.key_in(vx);
         let key_state = self.keyboard[key];
 
         if key_state == 0 {
@@ -443,6 +740,17 @@ impl Cpu {
         self.keyboard[key] = 0;
     }
 
+    /*
+    * The key Ex9E and ExA1 check, the low nibble of Vx as on the COSMAC VIP
+    */
+    fn key_in(&mut self, vx: usize) -> usize {
+        let value = self.registers[vx];
+        if value as usize >= KEY_COUNT {
+            self.suspicious(Finding::KeyOutOfRange, format!("checks key {:02X}, there are only 16", value));
+        }
+        (value & 0xF) as usize
+    }
+
     /*
     *   Fx07 - LD Vx, DT
     *   Set Vx = delay timer value.
@@ -476,6 +784,7 @@ impl Cpu {
     fn op_fx15(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         self.delay_timer = self.registers[vx];
+        trace!(target: logging::TIMERS, "Delay timer set to {}", self.delay_timer);
     }
 
     /*
@@ -485,6 +794,7 @@ impl Cpu {
     fn op_fx18(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         self.sound_timer = self.registers[vx];
+        debug!(target: logging::TIMERS, "Sound timer set to {}", self.sound_timer);
     }
 
     /*
@@ -498,13 +808,16 @@ impl Cpu {
 
     /*
     *   Fx29 - LD F, Vx
-    *   Set I = location of sprite for digit Vx.
+    *   Set I = location of sprite for digit Vx, using the low nibble of Vx.
     */
     fn op_fx29(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let digit = self.registers[vx];
+        if digit > 0xF {
+            self.suspicious(Finding::FontDigit, format!("points I at the font character for {:02X}, there are only 16", digit));
+        }
 
-        self.index = constants::FONTSET_START_ADDRESS.wrapping_add(5 * digit) as u16;
+        self.index = constants::FONTSET_START_ADDRESS as u16 + 5 * (digit & 0xF) as u16;
     }
 
     /*
@@ -515,9 +828,9 @@ impl Cpu {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let value = self.registers[vx];
 
-        self.memory[self.index as usize] = value / 100;
-        self.memory[(self.index + 1) as usize] = (value / 10) % 10;
-        self.memory[(self.index + 2) as usize] = value % 10;
+        self.write_memory(self.index as usize, value / 100);
+        self.write_memory(self.index as usize + 1, (value / 10) % 10);
+        self.write_memory(self.index as usize + 2, value % 10);
     }
 
     /*
@@ -528,8 +841,9 @@ impl Cpu {
         let vx = (self.opcode & 0x0F00) >> 8;
 
         for register in 0..=vx {
-            self.memory[(self.index + register) as usize] = self.registers[register as usize];
+            self.write_memory(self.index as usize + register as usize, self.registers[register as usize]);
         }
+        self.advance_index(vx);
     }
 
     /*
@@ -540,7 +854,19 @@ impl Cpu {
         let vx = (self.opcode & 0x0F00) >> 8;
 
         for register in 0..=vx {
-            self.registers[register as usize] = self.memory[(self.index + register) as usize];
+            self.registers[register as usize] = self.read_memory(self.index as usize + register as usize);
+        }
+        self.advance_index(vx);
+    }
+
+    /*
+    * Move I past the registers Fx55 or Fx65 transferred, as the quirks say
+    */
+    fn advance_index(&mut self, vx: u16) {
+        if self.quirks.memory_increment_by_x {
+            self.index = self.index.wrapping_add(vx);
+        } else if !self.quirks.memory_leave_i_unchanged {
+            self.index = self.index.wrapping_add(vx + 1);
         }
     }
 
@@ -550,10 +876,21 @@ impl Cpu {
     */
     pub fn cycle(&mut self) {
         // Fetch
-        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | self.memory[(self.pc + 1) as usize] as u16;
+        self.instruction = self.pc;
+        if self.strict.is_some() {
+            self.check_fetch();
+        }
+        self.opcode = (self.read_memory(self.pc as usize) as u16) << 8 | self.read_memory(self.pc as usize + 1) as u16;
+        trace!(target: logging::CPU, "{:03X}  {:04X}  {}", self.pc, self.opcode, disasm::disassemble(self.opcode));
 
         // Increment the program counter
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+        self.cycles += 1;
+        self.executed_at[self.instruction as usize % MEMORY_SIZE] = self.cycles;
+        self.executed_at[(self.instruction as usize + 1) % MEMORY_SIZE] = self.cycles;
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(self.instruction);
+        }
 
         // Decode and execute.
         match self.opcode & 0xF000 {
@@ -561,9 +898,7 @@ impl Cpu {
                 match self.opcode & 0x00FF {
                     0xE0 => self.op_00e0(),
                     0xEE => self.op_00ee(),
-                    _ => {
-                        panic!("Unsupported 0x0 opcode: {}", (self.opcode & 0x00FF));
-                    },
+                    _ => self.not_an_instruction(),
                 }
             }
             0x1000 => self.op_1nnn(),
@@ -584,9 +919,7 @@ impl Cpu {
                     0x6 => self.op_8xy6(),
                     0x7 => self.op_8xy7(),
                     0xE => self.op_8xye(),
-                    _ => {
-                        panic!("Unsupported 0x8 opcode: {}", (self.opcode & 0x000F));
-                    },
+                    _ => self.not_an_instruction(),
                 }
             },
             0x9000 => self.op_9xy0(),
@@ -598,9 +931,7 @@ impl Cpu {
                 match self.opcode & 0x00FF {
                     0x9E => self.op_ex9e(),
                     0xA1 => self.op_exa1(),
-                    _ => {
-                        panic!("Unsupported 0xE opcode: {}", (self.opcode & 0x00FF));
-                    },
+                    _ => self.not_an_instruction(),
                 }
             },
             0xF000 => {
@@ -614,24 +945,99 @@ impl Cpu {
                     0x33 => self.op_fx33(),
                     0x55 => self.op_fx55(),
                     0x65 => self.op_fx65(),
-                    _ => {
-                        panic!("Unsupported 0xF opcode: {}", (self.opcode & 0x00FF));
-                    },
+                    _ => self.not_an_instruction(),
                 }
             }
-            _ => {
-                panic!("Unsupported opcode: {}", (self.opcode & 0x00FF));
-            },
+            _ => self.not_an_instruction(),
         }
 
-        // Decrement delay and sound timers if required.
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(self.instruction, self.sp, self.pc);
+        }
+    }
+
+    /*
+    *   Tick timers
+    *   Decrement the delay and sound timers and start a new frame for the
+    *   vblank quirk, called at 60 Hz.
+    */
+    pub fn tick_timers(&mut self) {
+        self.vblank = true;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.frame();
+        }
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
+    }
+
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run one 8xy4, 8xy5 or 8xy7 with some registers set first
+    fn alu(opcode: u16, registers: &[(usize, u8)]) -> Cpu {
+        let mut cpu = Cpu::new();
+        for &(register, value) in registers {
+            cpu.registers[register] = value;
+        }
+        cpu.opcode = opcode;
+        match opcode & 0xF {
+            0x4 => cpu.op_8xy4(),
+            0x5 => cpu.op_8xy5(),
+            0x7 => cpu.op_8xy7(),
+            _ => unreachable!(),
+        }
+        cpu
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        let cpu = alu(0x8124, &[(1, 0xFF), (2, 0x02)]);
+        assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0x01, 1));
+        let cpu = alu(0x8124, &[(1, 0x10), (2, 0x20), (0xF, 1)]);
+        assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0x30, 0));
+    }
+
+    #[test]
+    fn subtract_of_equal_values_does_not_borrow() {
+        let cpu = alu(0x8125, &[(1, 7), (2, 7)]);
+        assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0, 1));
+        let cpu = alu(0x8115, &[(1, 7)]);
+        assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0, 1));
+        let cpu = alu(0x8125, &[(1, 3), (2, 7)]);
+        assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0xFC, 0));
     }
 
+    #[test]
+    fn subtract_reversed_stores_in_vx() {
+        let cpu = alu(0x8127, &[(1, 3), (2, 10)]);
+        assert_eq!((cpu.registers[1], cpu.registers[2], cpu.registers[0xF]), (7, 10, 1));
+        let cpu = alu(0x8127, &[(1, 10), (2, 3)]);
+        assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0xF9, 0));
+    }
+
+    #[test]
+    fn soft_reset_starts_afresh() {
+        let mut cpu = Cpu::new();
+        cpu.vblank = false;
+        cpu.instruction = 0x300;
+        cpu.suspicious(Finding::InvalidInstruction, "test".to_string());
+        cpu.reset(true);
+        assert!(cpu.vblank);
+        assert_eq!(cpu.instruction, ROM_START);
+        assert!(cpu.warned_at.is_empty());
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        assert_eq!(alu(0x8F14, &[(0xF, 0xFF), (1, 0x02)]).registers[0xF], 1);
+        assert_eq!(alu(0x8F15, &[(0xF, 5), (1, 3)]).registers[0xF], 1);
+        assert_eq!(alu(0x8F17, &[(0xF, 5), (1, 3)]).registers[0xF], 0);
+    }
 }
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use crate::cartridge::OctoOptions;
+use crate::constants::{CYCLES_PER_FRAME, KEY_COUNT};
+use crate::frontend::GameKeys;
+use crate::options::Options;
+use crate::palette::Palette;
+use crate::quirks::{Platform, QuirkOverrides, Quirks};
+
+// Programs shipped with the emulator, in the chip-8-database programs.json format.
+const BUNDLED_PROGRAMS: &str = include_str!("../data/programs.json");
+
+// Our own titles, in the same format, in the home directory. Entries here
+// replace bundled entries for the same ROM.
+const OVERRIDE_FILE: &str = ".chip8_programs.json";
+
+#[derive(Deserialize)]
+struct Program {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, RomInfo>, // By SHA1 of the ROM file
+}
+
+/*
+* What the database knows about one ROM file.
+*/
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RomInfo {
+    #[serde(skip)]
+    pub title: String,
+    pub platforms: Vec<String>, // Platform ids, the preferred one first
+    pub tickrate: Option<u32>, // Instructions per frame
+    pub keys: HashMap<String, usize>, // Roles such as "up" or "a" to CHIP-8 keys
+    pub colors: Option<RomColors>,
+    pub quirky_platforms: HashMap<String, QuirkOverrides>, // Quirks that differ from the platform's
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RomColors {
+    pub pixels: Vec<String>, // Hex colours, background first
+}
+
+/*
+* ROM metadata keyed by the SHA1 of the ROM file.
+*/
+pub struct Database {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl Database {
+    /*
+    * Load the bundled database, then the override file if there is one.
+    */
+    pub fn load() -> Self {
+        let mut database = Database { roms: HashMap::new() };
+        database.add(BUNDLED_PROGRAMS, "bundled database");
+
+        if let Some(path) = override_file().filter(|path| path.is_file()) {
+            match fs::read_to_string(&path) {
+                Ok(json) => database.add(&json, &path.display().to_string()),
+                Err(error) => warn!("Cannot read {}: {}", path.display(), error),
+            }
+        }
+        database
+    }
+
+    fn add(&mut self, json: &str, source: &str) {
+        let programs: Vec<Program> = match serde_json::from_str(json) {
+            Ok(programs) => programs,
+            Err(error) => {
+                warn!("Ignoring {}: {}", source, error);
+                return;
+            },
+        };
+
+        for program in programs {
+            for (hash, mut rom) in program.roms {
+                rom.title = program.title.clone();
+                self.roms.insert(hash.to_ascii_lowercase(), rom);
+            }
+        }
+    }
+
+    pub fn lookup(&self, hash: &str) -> Option<&RomInfo> {
+        self.roms.get(hash)
+    }
+}
+
+fn rom_key(rom: &RomInfo, role: &str) -> Option<usize> {
+    rom.keys.get(role).copied().filter(|&key| key < KEY_COUNT)
+}
+
+fn override_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(OVERRIDE_FILE))
+}
+
+/*
+* Lower case hex SHA1 of a ROM image, the key the database uses.
+*/
+pub fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/*
+* The settings a game is played with. Options given on the command line win,
+* then the database entry for the ROM, then its platform's defaults. A
+* platform given on the command line replaces the database's.
+*/
+pub struct Profile {
+    pub name: String, // Shown in the overlay
+    pub quirks: Quirks,
+    pub cycles_per_frame: u32,
+    pub palette: Palette,
+    pub keys: GameKeys,
+}
+
+impl Profile {
+    pub fn new(options: &Options, rom: Option<&RomInfo>) -> Self {
+        let rom = match rom {
+            Some(rom) => rom,
+            None => {
+                return Profile {
+                    name: match options.platform {
+                        Some(platform) => format!("CHIP-8 ({})", platform.id),
+                        None => "CHIP-8".to_string(),
+                    },
+                    quirks: options.platform.map_or(Quirks::default(), |platform| platform.quirks),
+                    cycles_per_frame: options.cycles_per_frame
+                        .or(options.platform.map(|platform| platform.tick_rate))
+                        .unwrap_or(CYCLES_PER_FRAME),
+                    palette: options.palette.unwrap_or_default(),
+                    keys: GameKeys::default(),
+                };
+            },
+        };
+
+        let platform = options.platform.or_else(|| rom.platforms.iter().find_map(|id| Platform::from_id(id)));
+        let quirks = match platform {
+            Some(platform) => rom.quirky_platforms.get(platform.id)
+                .map_or(platform.quirks, |overrides| platform.quirks.with_overrides(overrides)),
+            None => Quirks::default(),
+        };
+        let palette = rom.colors.as_ref()
+            .and_then(|colors| Palette::parse(&colors.pixels.join(",")).ok());
+
+        Profile {
+            name: match platform {
+                Some(platform) => format!("{} ({})", rom.title, platform.id),
+                None => rom.title.clone(),
+            },
+            quirks,
+            cycles_per_frame: options.cycles_per_frame
+                .or(rom.tickrate)
+                .or(platform.map(|platform| platform.tick_rate))
+                .unwrap_or(CYCLES_PER_FRAME),
+            palette: options.palette.or(palette).unwrap_or_default(),
+            keys: GameKeys {
+                up: rom_key(rom, "up"),
+                down: rom_key(rom, "down"),
+                left: rom_key(rom, "left"),
+                right: rom_key(rom, "right"),
+                a: rom_key(rom, "a"),
+                b: rom_key(rom, "b"),
+            },
+        }
+    }
+
+    /*
+    * Apply the options an Octo cartridge was saved with, which are more
+    * specific than the database's. Command line options still win.
+    */
+    pub fn with_cartridge(mut self, options: &Options, cartridge: &OctoOptions) -> Self {
+        if options.platform.is_none() {
+            self.quirks = cartridge.quirks(self.quirks);
+        }
+        if let (None, Some(tickrate)) = (options.cycles_per_frame, cartridge.tickrate) {
+            self.cycles_per_frame = tickrate;
+        }
+        if let (None, Some(palette)) = (options.palette, cartridge.palette()) {
+            self.palette = palette;
+        }
+        self
+    }
+
+    /*
+    * Look the ROM up by hash and build its profile, then apply the cartridge's
+    * own options if it came from one, and last the quirks given on the command
+    * line.
+    */
+    pub fn for_rom(options: &Options, database: &Database, hash: &str, cartridge: Option<&OctoOptions>) -> Self {
+        let rom = database.lookup(hash);
+        match rom {
+            Some(rom) => info!("Found {} in the ROM database", rom.title),
+            None => info!("ROM {} is not in the database, using defaults", hash),
+        }
+        let mut profile = Self::new(options, rom);
+        if let Some(cartridge) = cartridge {
+            profile = profile.with_cartridge(options, cartridge);
+        }
+        profile.quirks = profile.quirks.with_overrides(&options.quirks);
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A two instruction ROM, CLS then a jump to itself
+    const ROM: [u8; 4] = [0x00, 0xE0, 0x12, 0x02];
+
+    fn options(args: &[&str]) -> Options {
+        let args: Vec<String> = ["chip8", "--config", "/dev/null"].iter().chain(args).map(|arg| arg.to_string()).collect();
+        Options::from_args(&args).unwrap()
+    }
+
+    fn database(json: &str) -> Database {
+        let mut database = Database { roms: HashMap::new() };
+        database.add(json, "test");
+        database
+    }
+
+    #[test]
+    fn bundled_database_parses() {
+        assert!(serde_json::from_str::<Vec<Program>>(BUNDLED_PROGRAMS).is_ok());
+    }
+
+    #[test]
+    fn bundled_hashes_resolve_to_their_platform() {
+        let programs: Vec<Program> = serde_json::from_str(BUNDLED_PROGRAMS).unwrap();
+        let database = database(BUNDLED_PROGRAMS);
+        for program in programs {
+            for (hash, rom) in program.roms {
+                let found = database.lookup(&hash.to_ascii_lowercase()).unwrap();
+                assert_eq!(found.platforms, rom.platforms, "{}", program.title);
+                if let Some(platform) = rom.platforms.iter().find_map(|id| Platform::from_id(id)) {
+                    let profile = Profile::for_rom(&options(&[]), &database, &hash.to_ascii_lowercase(), None);
+                    assert_eq!(profile.name, format!("{} ({})", program.title, platform.id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_hash_resolves_to_its_platform() {
+        let hash = sha1_hex(&ROM);
+        let database = database(&format!(r#"[{{
+            "title": "Halt",
+            "roms": {{ "{}": {{ "platforms": ["superchip", "xochip"], "tickrate": 30 }} }}
+        }}]"#, hash.to_uppercase()));
+
+        let profile = Profile::for_rom(&options(&[]), &database, &hash, None);
+        let superchip = Platform::from_id("superchip").unwrap();
+        assert_eq!(profile.name, "Halt (superchip)");
+        assert_eq!(profile.quirks, superchip.quirks);
+        assert_eq!(profile.cycles_per_frame, 30);
+    }
+
+    #[test]
+    fn platform_option_replaces_the_database_platform() {
+        let hash = sha1_hex(&ROM);
+        let database = database(&format!(r#"[{{ "title": "Halt", "roms": {{ "{}": {{ "platforms": ["superchip"] }} }} }}]"#, hash));
+
+        let profile = Profile::for_rom(&options(&["--platform", "originalChip8"]), &database, &hash, None);
+        assert_eq!(profile.quirks, Platform::from_id("originalChip8").unwrap().quirks);
+    }
+
+    #[test]
+    fn unknown_hash_uses_defaults() {
+        let profile = Profile::for_rom(&options(&[]), &database("[]"), &sha1_hex(&ROM), None);
+        assert_eq!(profile.name, "CHIP-8");
+        assert_eq!(profile.cycles_per_frame, CYCLES_PER_FRAME);
+    }
+}
@@ -0,0 +1,185 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use crate::constants::*;
+use crate::cpu::Cpu;
+use crate::disasm;
+use crate::display::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+const WINDOW_WIDTH: u32 = 920;
+const WINDOW_HEIGHT: u32 = 600;
+const TEXT_SCALE: u32 = 2;
+const CHAR_WIDTH: i32 = ((GLYPH_WIDTH + 1) * TEXT_SCALE) as i32;
+const LINE_HEIGHT: i32 = ((GLYPH_HEIGHT + 2) * TEXT_SCALE) as i32;
+const MARGIN: i32 = 8;
+
+// Left edge of each panel, in characters from the window edge.
+const REGISTERS_COLUMN: i32 = 0;
+const DISASSEMBLY_COLUMN: i32 = 28;
+const MEMORY_COLUMN: i32 = 59;
+
+const MEMORY_ROW_BYTES: usize = 16;
+const MEMORY_ROWS: usize = MEMORY_SIZE / MEMORY_ROW_BYTES;
+const DISASSEMBLY_BEFORE_PC: usize = 8; // Instructions shown above the current one
+
+// A byte counts as recently written for this many instructions after the write.
+const RECENT_WRITE_CYCLES: u64 = 600;
+
+const BACKGROUND: Color = Color::RGB(0x1E, 0x1E, 0x1E);
+const HEADING: Color = Color::RGB(0xFF, 0xCC, 0x00);
+const TEXT: Color = Color::RGB(0xDD, 0xDD, 0xDD);
+const DIM: Color = Color::RGB(0x70, 0x70, 0x70);
+const CURRENT: Color = Color::RGB(0x33, 0xFF, 0x33); // The instruction at PC
+const INDEX: Color = Color::RGB(0x33, 0xCC, 0xFF); // The byte I points at
+const WRITTEN: Color = Color::RGB(0xFF, 0x55, 0x33); // Recently written bytes
+const MODIFIED: Color = Color::RGB(0xFF, 0x55, 0xFF); // Code written after it ran
+const HIGHLIGHT: Color = Color::RGB(0x3A, 0x3A, 0x3A);
+
+/*
+* A second window showing the CPU state: registers, stack, timers, a
+* disassembly around PC and a scrollable hex view of memory. Code the program
+* has written over after running it is highlighted in both.
+*/
+pub struct Debugger {
+    canvas: Canvas<Window>,
+    memory_scroll: usize, // First row of the hex view
+}
+
+impl Debugger {
+    pub fn new(video_subsystem: &sdl2::VideoSubsystem) -> Self {
+        let window = video_subsystem.window("Chip-8 Debugger", WINDOW_WIDTH, WINDOW_HEIGHT)
+            .position_centered()
+            .build()
+            .unwrap();
+
+        Debugger {
+            canvas: window.into_canvas().build().unwrap(),
+            memory_scroll: ROM_START as usize / MEMORY_ROW_BYTES,
+        }
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    fn visible_rows(&self) -> usize {
+        ((WINDOW_HEIGHT as i32 - 2 * MARGIN) / LINE_HEIGHT) as usize - 1
+    }
+
+    /*
+    * Move the hex view by a number of rows, negative scrolls up.
+    */
+    pub fn scroll(&mut self, rows: i32) {
+        let last_row = MEMORY_ROWS - self.visible_rows();
+        self.memory_scroll = (self.memory_scroll as i32 + rows).clamp(0, last_row as i32) as usize;
+    }
+
+    fn text(&mut self, text: &str, column: i32, line: i32, color: Color) {
+        display::draw_text(&mut self.canvas,
+                           text,
+                           MARGIN + column * CHAR_WIDTH,
+                           MARGIN + line * LINE_HEIGHT,
+                           TEXT_SCALE,
+                           color);
+    }
+
+    fn highlight_line(&mut self, column: i32, line: i32, characters: i32) {
+        self.canvas.set_draw_color(HIGHLIGHT);
+        self.canvas.fill_rect(Rect::new(MARGIN + column * CHAR_WIDTH - TEXT_SCALE as i32,
+                                        MARGIN + line * LINE_HEIGHT - TEXT_SCALE as i32,
+                                        (characters * CHAR_WIDTH) as u32,
+                                        LINE_HEIGHT as u32)).unwrap();
+    }
+
+    pub fn draw(&mut self, cpu: &Cpu) {
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+
+        self.draw_registers(cpu);
+        self.draw_disassembly(cpu);
+        self.draw_memory(cpu);
+
+        self.canvas.present();
+    }
+
+    fn draw_registers(&mut self, cpu: &Cpu) {
+        let column = REGISTERS_COLUMN;
+        self.text("REGISTERS", column, 0, HEADING);
+
+        for (i, value) in cpu.registers().iter().enumerate() {
+            let line = 1 + (i / 4) as i32;
+            let offset = (i % 4) as i32 * 7;
+            self.text(&format!("V{:X} {:02X}", i, value), column + offset, line, TEXT);
+        }
+
+        self.text(&format!("I  {:03X}  PC {:03X}", cpu.index(), cpu.pc()), column, 6, TEXT);
+        self.text(&format!("SP {:X}    DT {:02X}  ST {:02X}", cpu.sp(), cpu.delay_timer, cpu.sound_timer), column, 7, TEXT);
+
+        self.text("STACK", column, 9, HEADING);
+        for (level, address) in cpu.stack().iter().enumerate() {
+            let in_use = level < cpu.sp() as usize;
+            let marker = if level + 1 == cpu.sp() as usize { ">" } else { " " };
+            self.text(&format!("{}{:X} {:03X}", marker, level, address), column, 10 + level as i32, if in_use { TEXT } else { DIM });
+        }
+    }
+
+    fn draw_disassembly(&mut self, cpu: &Cpu) {
+        let column = DISASSEMBLY_COLUMN;
+        self.text("DISASSEMBLY", column, 0, HEADING);
+
+        // Keep the same alignment as PC, instructions are two bytes
+        let pc = cpu.pc() as usize;
+        let mut address = pc.saturating_sub(DISASSEMBLY_BEFORE_PC * 2);
+        for line in 1..=self.visible_rows() as i32 {
+            if address + 1 >= MEMORY_SIZE {
+                break;
+            }
+            if address == pc {
+                self.highlight_line(column, line, 30);
+            }
+            let color = if address == pc {
+                CURRENT
+            } else if cpu.modified_code()[address] != 0 || cpu.modified_code()[address + 1] != 0 {
+                MODIFIED
+            } else if cpu.executed_at()[address] == 0 {
+                DIM // Not run yet, possibly data
+            } else {
+                TEXT
+            };
+            self.text(&disasm::disassemble_at(cpu.memory(), address), column, line, color);
+            address += 2;
+        }
+    }
+
+    fn draw_memory(&mut self, cpu: &Cpu) {
+        let column = MEMORY_COLUMN;
+        self.text("MEMORY", column, 0, HEADING);
+
+        let pc = cpu.pc() as usize;
+        let index = cpu.index() as usize;
+        let rows = self.visible_rows();
+        for row in 0..rows.min(MEMORY_ROWS - self.memory_scroll) {
+            let line = 1 + row as i32;
+            let start = (self.memory_scroll + row) * MEMORY_ROW_BYTES;
+            self.text(&format!("{:03X}:", start), column, line, DIM);
+
+            for offset in 0..MEMORY_ROW_BYTES {
+                let address = start + offset;
+                let written_at = cpu.written_at()[address];
+                let color = if address == pc || address == pc + 1 {
+                    CURRENT
+                } else if address == index {
+                    INDEX
+                } else if cpu.modified_code()[address] != 0 {
+                    MODIFIED
+                } else if written_at != 0 && cpu.cycles() - written_at < RECENT_WRITE_CYCLES {
+                    WRITTEN
+                } else {
+                    TEXT
+                };
+                self.text(&format!("{:02X}", cpu.memory()[address]), column + 5 + offset as i32 * 3, line, color);
+            }
+        }
+    }
+}
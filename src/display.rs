@@ -1,70 +1,494 @@
 extern crate sdl2;
 
-use sdl2::rect::Point;
-use sdl2::video::Window;
-use sdl2::pixels::Color;
+use std::path::PathBuf;
+use log::{debug, info, trace};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use crate::cpu::Cpu;
+use crate::debugger::Debugger;
+use crate::sprite_viewer::SpriteViewer;
+use crate::logging;
+use crate::frontend::{GameKeys, InputEvent, InputSource, Keymap, Renderer, SpriteViewerAction};
+use crate::palette::Rgb;
 
-const SCALE_FACTOR: u32 = 10;
+pub const DEFAULT_SCALE: u32 = 10;
+
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+
+/*
+* A 3x5 pixel font for on-screen text, so no system font is needed.
+* Each row holds the glyph's three pixels in its low bits, left to right.
+* Lowercase letters are drawn with the uppercase glyphs.
+*/
+const FONT: [(char, [u8; 5]); 60] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b111, 0b001, 0b010, 0b000, 0b010]),
+    ('(', [0b010, 0b100, 0b100, 0b100, 0b010]),
+    (')', [0b010, 0b001, 0b001, 0b001, 0b010]),
+    ('[', [0b110, 0b100, 0b100, 0b100, 0b110]),
+    (']', [0b011, 0b001, 0b001, 0b001, 0b011]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+    ('*', [0b000, 0b101, 0b010, 0b101, 0b000]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('$', [0b011, 0b110, 0b010, 0b011, 0b110]),
+    ('&', [0b010, 0b101, 0b010, 0b101, 0b011]),
+];
+
+/*
+* Look up the glyph for a character, unknown characters are drawn as '?'.
+*/
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    FONT.iter()
+        .find(|(glyph_char, _)| *glyph_char == c)
+        .or_else(|| FONT.iter().find(|(glyph_char, _)| *glyph_char == '?'))
+        .map(|(_, rows)| *rows)
+        .unwrap()
+}
+
+/*
+* Width in window pixels of a line of text, scale being the size of one font pixel.
+*/
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    (text.chars().count() as u32 * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale
+}
+
+/*
+* Draw a line of text with the built-in pixel font, x and y being the top left
+* corner in window pixels.
+*/
+pub fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, scale: u32, color: Color) {
+    let advance = (GLYPH_WIDTH + 1) * scale;
+
+    let mut pixels = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        let left = x + (i as u32 * advance) as i32;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) != 0 {
+                    pixels.push(Rect::new(left + (column * scale) as i32,
+                                          y + (row as u32 * scale) as i32,
+                                          scale,
+                                          scale));
+                }
+            }
+        }
+    }
+    canvas.set_draw_color(color);
+    canvas.fill_rects(&pixels).unwrap();
+}
+
+/*
+* A streaming RGB24 texture for frames of the given size.
+*/
+fn frame_texture(texture_creator: &TextureCreator<WindowContext>, width: usize, height: usize) -> Texture<'_> {
+    texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32).unwrap()
+}
 
 pub struct Display {
-    pub sdl_context: sdl2::Sdl,
-    canvas: sdl2::render::Canvas<Window>,
-    window_width: u32,
-    #[allow(dead_code)]
-    window_height: u32
+    sdl_context: sdl2::Sdl,
+    video_subsystem: sdl2::VideoSubsystem,
+    canvas: Canvas<Window>,
+    texture_creator: &'static TextureCreator<WindowContext>, // Leaked once, so the texture can borrow it for good
+    texture: Texture<'static>, // Streaming texture each frame is uploaded to, recreated when the frame size changes
+    frame_width: usize,
+    frame_height: usize,
+    pixels: Vec<u8>, // RGB24 staging buffer for the texture
+    integer_scale: bool,
+    overlay: Vec<String>,
+    debugger: Option<Debugger>,
+    sprite_viewer: Option<SpriteViewer>,
 }
 
 impl Display {
-    pub fn new(title: &String, window_width: u32, window_height: u32) -> Self {
+    /*
+    * Open a resizable window, initially scale times the framebuffer size.
+    * The framebuffer keeps its aspect ratio with letterboxing, and with
+    * integer_scale it is only ever scaled by whole numbers. With vsync, presenting
+    * waits for the display's vertical blank so frames never tear.
+    */
+    pub fn new(title: &str, window_width: u32, window_height: u32, scale: u32, integer_scale: bool, vsync: bool) -> Self {
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
 
-        let window = video_subsystem.window(title, window_width * SCALE_FACTOR, window_height * SCALE_FACTOR)
+        let window = video_subsystem.window(title, window_width * scale, window_height * scale)
             .position_centered()
+            .resizable()
             .opengl()
             .build()
             .unwrap();
 
-            let mut canvas: sdl2::render::Canvas<Window> = window.into_canvas()
+            let mut canvas_builder = window.into_canvas();
+            if vsync {
+                canvas_builder = canvas_builder.present_vsync();
+            }
+            let mut canvas: Canvas<Window> = canvas_builder
             .build()
             .unwrap();
 
-            canvas.set_scale(10.0, 10.0).unwrap();
+            canvas.set_blend_mode(BlendMode::Blend);
             canvas.set_draw_color(Color::RGB(0, 0, 0));
             canvas.clear();
             canvas.present();
 
+            info!(target: logging::DISPLAY, "Opened a {}x{} window{}", window_width * scale, window_height * scale,
+                  if vsync { " with vsync" } else { "" });
+
+            let texture_creator: &'static TextureCreator<WindowContext> = Box::leak(Box::new(canvas.texture_creator()));
+            let texture = frame_texture(texture_creator, window_width as usize, window_height as usize);
+
             Display {
-                sdl_context: sdl_context,
-                canvas: canvas,
-                window_width: window_width,
-                window_height: window_height
+                sdl_context,
+                video_subsystem,
+                canvas,
+                texture_creator,
+                texture,
+                frame_width: window_width as usize,
+                frame_height: window_height as usize,
+                pixels: Vec::new(),
+                integer_scale,
+                overlay: Vec::new(),
+                debugger: None,
+                sprite_viewer: None,
             }
     }
 
-    pub fn redraw(&mut self, video_buffer: &[u8]) {
+    /*
+    * Follow a change of framebuffer size, e.g. switching to a high resolution mode.
+    */
+    fn resize_frame(&mut self, width: usize, height: usize) {
+        self.texture = frame_texture(self.texture_creator, width, height);
+        self.frame_width = width;
+        self.frame_height = height;
+        debug!(target: logging::DISPLAY, "Framebuffer is now {}x{}", width, height);
+    }
+
+    /*
+    * Where the frame goes in the window: as large as fits while keeping its
+    * aspect ratio, centred with letterbox bars around it.
+    */
+    fn frame_rect(&self) -> Rect {
+        let (window_width, window_height) = self.canvas.output_size().unwrap();
+        let mut scale = (window_width as f32 / self.frame_width as f32)
+            .min(window_height as f32 / self.frame_height as f32);
+        if self.integer_scale {
+            scale = scale.floor().max(1.0);
+        }
+
+        let width = (self.frame_width as f32 * scale) as u32;
+        let height = (self.frame_height as f32 * scale) as u32;
+        Rect::new((window_width as i32 - width as i32) / 2,
+                  (window_height as i32 - height as i32) / 2,
+                  width.max(1),
+                  height.max(1))
+    }
+
+    /*
+    * Size of one font pixel, so text stays readable at any window size.
+    */
+    fn text_scale(&self) -> u32 {
+        let (_, window_height) = self.canvas.output_size().unwrap();
+        (window_height / 160).max(2)
+    }
+
+    /*
+    * Draw a line of overlay text on a dark backing box.
+    * x and y are the top left corner in window pixels.
+    */
+    fn draw_overlay_line(&mut self, text: &str, x: i32, y: i32, color: Color) {
+        let scale = self.text_scale();
+
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        self.canvas.fill_rect(Rect::new(x, y, text_width(text, scale) + 2 * scale, (GLYPH_HEIGHT + 2) * scale)).unwrap();
+        draw_text(&mut self.canvas, text, x + scale as i32, y + scale as i32, scale, color);
+    }
+
+    /*
+    * Create the input source reading events from this window.
+    */
+    pub fn input(&self, keymap: Keymap) -> SdlInput {
+        SdlInput {
+            event_pump: self.sdl_context.event_pump().unwrap(),
+            main_window_id: self.canvas.window().id(),
+            keymap,
+            game_keys: GameKeys::default(),
+        }
+    }
+
+}
+
+impl Renderer for Display {
+
+    fn redraw(&mut self, frame: &[Rgb], width: usize, height: usize) {
+        if (width, height) != (self.frame_width, self.frame_height) {
+            self.resize_frame(width, height);
+        }
+
+        self.pixels.clear();
+        for pixel in frame.iter().take(width * height) {
+            self.pixels.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+        }
+        let frame_rect = self.frame_rect();
         self.canvas.set_draw_color(Color::RGB(0, 0, 0));
         self.canvas.clear();
-        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
 
-        let mut x = 0; // x position of the pixel
-        let mut y = 0; // y position of the pixel
+        self.texture.update(None, &self.pixels, width * 3).unwrap();
+        self.canvas.copy(&self.texture, None, frame_rect).unwrap();
 
-        let display_divisor: i32 = (self.window_width as i32 - 1).into();
+        let margin = self.text_scale() as i32;
+        let line_height = (GLYPH_HEIGHT as i32 + 2) * margin;
+        for (i, line) in self.overlay.clone().iter().enumerate() {
+            self.draw_overlay_line(line,
+                           frame_rect.x() + margin,
+                           frame_rect.y() + margin + i as i32 * line_height,
+                           Color::RGB(255, 255, 255));
+        }
 
-        for pixel in video_buffer.iter() {
-            if *pixel != 0 {
-                self.canvas.draw_point(Point::new(x, y)).unwrap();
-            }
-            if x != 0 && (x % display_divisor) == 0 {
-                x = 0;
-                y += 1;
-            } else {
-                x += 1;
+        self.canvas.present();
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen).unwrap();
+        debug!(target: logging::DISPLAY, "Fullscreen {:?}", fullscreen);
+    }
+
+    fn show_overlay(&mut self, lines: &[String]) {
+        self.overlay = lines.to_vec();
+    }
+
+    fn toggle_debugger(&mut self) {
+        self.debugger = match self.debugger {
+            Some(_) => None,
+            None => Some(Debugger::new(&self.video_subsystem)),
+        };
+    }
+
+    fn scroll_debugger(&mut self, rows: i32) {
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.scroll(rows);
+        }
+    }
+
+    fn update_debugger(&mut self, cpu: &Cpu) {
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.draw(cpu);
+        }
+        if let Some(sprite_viewer) = self.sprite_viewer.as_mut() {
+            sprite_viewer.draw(cpu);
+        }
+    }
+
+    fn toggle_sprite_viewer(&mut self) {
+        self.sprite_viewer = match self.sprite_viewer {
+            Some(_) => None,
+            None => Some(SpriteViewer::new(&self.video_subsystem)),
+        };
+    }
+
+    fn sprite_viewer(&mut self, action: SpriteViewerAction) {
+        if let Some(sprite_viewer) = self.sprite_viewer.as_mut() {
+            sprite_viewer.handle(action);
+        }
+    }
+
+    fn close_window(&mut self, window_id: u32) {
+        if self.debugger.as_ref().is_some_and(|debugger| debugger.window_id() == window_id) {
+            self.debugger = None;
+        }
+        if self.sprite_viewer.as_ref().is_some_and(|sprite_viewer| sprite_viewer.window_id() == window_id) {
+            self.sprite_viewer = None;
+        }
+    }
+
+    fn scroll_window(&mut self, window_id: u32, rows: i32) {
+        match self.sprite_viewer.as_mut() {
+            Some(sprite_viewer) if sprite_viewer.window_id() == window_id => sprite_viewer.handle(SpriteViewerAction::Scroll(rows.signum())),
+            _ => self.scroll_debugger(rows),
+        }
+    }
+
+}
+
+/*
+* Keyboard and window events from SDL.
+*/
+pub struct SdlInput {
+    event_pump: sdl2::EventPump,
+    main_window_id: u32, // Closing any other window only closes that window
+    keymap: Keymap,
+    game_keys: GameKeys,
+}
+
+impl SdlInput {
+    /*
+    * The CHIP-8 key the current game binds to an arrow or action key, if any.
+    */
+    fn game_key(game_keys: &GameKeys, keycode: Keycode) -> Option<usize> {
+        match keycode {
+            Keycode::Up => game_keys.up,
+            Keycode::Down => game_keys.down,
+            Keycode::Left => game_keys.left,
+            Keycode::Right => game_keys.right,
+            Keycode::Space => game_keys.a,
+            Keycode::LShift | Keycode::RShift => game_keys.b,
+            _ => None,
+        }
+    }
+
+    fn chip8_key(keymap: &Keymap, keycode: Keycode) -> Option<usize> {
+        let name = keycode.name();
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => keymap.key_for_char(c),
+            _ => None,
+        }
+    }
+
+    /*
+    * Emulator controls bound to keys outside the CHIP-8 keypad.
+    */
+    fn hotkey(keycode: Keycode) -> Option<InputEvent> {
+        match keycode {
+            Keycode::F11 => Some(InputEvent::ToggleFullscreen),
+            Keycode::P => Some(InputEvent::TogglePause),
+            Keycode::N => Some(InputEvent::StepFrame),
+            Keycode::M => Some(InputEvent::StepInstruction),
+            Keycode::Tab => Some(InputEvent::FastForward(true)),
+            Keycode::L => Some(InputEvent::CycleSlowMotion),
+            Keycode::O => Some(InputEvent::ToggleOverlay),
+            Keycode::F12 => Some(InputEvent::ToggleDebugger),
+            Keycode::PageUp => Some(InputEvent::ScrollDebugger(-16)),
+            Keycode::PageDown => Some(InputEvent::ScrollDebugger(16)),
+            Keycode::F10 => Some(InputEvent::ToggleSpriteViewer),
+            Keycode::Up => Some(InputEvent::Navigate(-1)),
+            Keycode::Down => Some(InputEvent::Navigate(1)),
+            Keycode::Return => Some(InputEvent::Select),
+            Keycode::F2 => Some(InputEvent::OpenLauncher),
+            Keycode::F5 => Some(InputEvent::Reset(true)),
+            Keycode::F6 => Some(InputEvent::Reset(false)),
+            Keycode::Left => Some(InputEvent::SpriteViewer(SpriteViewerAction::Height(-1))),
+            Keycode::Right => Some(InputEvent::SpriteViewer(SpriteViewerAction::Height(1))),
+            Keycode::B => Some(InputEvent::SpriteViewer(SpriteViewerAction::ToggleLarge)),
+            Keycode::I => Some(InputEvent::SpriteViewer(SpriteViewerAction::JumpToIndex)),
+            Keycode::Home => Some(InputEvent::SpriteViewer(SpriteViewerAction::JumpToFont)),
+            _ => None,
+        }
+    }
+}
+
+impl InputSource for SdlInput {
+
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => events.push(InputEvent::Quit),
+                Event::Window {
+                    win_event: WindowEvent::Close,
+                    window_id,
+                    ..
+                } => events.push(if window_id == self.main_window_id { InputEvent::Quit } else { InputEvent::CloseWindow(window_id) }),
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => events.push(InputEvent::Redraw),
+                Event::DropFile { filename, .. } => events.push(InputEvent::LoadRom(PathBuf::from(filename))),
+                Event::MouseWheel { window_id, y, .. } => events.push(InputEvent::ScrollWindow(window_id, -y * 2)),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } => {
+                    debug!(target: logging::INPUT, "Key down {:?}", keycode);
+                    let key = Self::game_key(&self.game_keys, keycode).or(Self::chip8_key(&self.keymap, keycode));
+                    let event = match key {
+                        Some(key) => Some(InputEvent::KeyDown(key)),
+                        None => Self::hotkey(keycode),
+                    };
+                    events.extend(event);
+                },
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => events.push(InputEvent::FastForward(false)),
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => events.extend(Self::game_key(&self.game_keys, keycode).or(Self::chip8_key(&self.keymap, keycode)).map(InputEvent::KeyUp)),
+                Event::KeyDown { .. } => {},
+                event => trace!(target: logging::INPUT, "Ignored {:?}", event),
             }
         }
 
-        self.canvas.present();
+        events
     }
 
-}
\ No newline at end of file
+    fn set_game_keys(&mut self, keys: &GameKeys) {
+        self.game_keys = *keys;
+    }
+
+}
@@ -0,0 +1,261 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use log::{error, info};
+use crate::archive;
+use crate::constants::*;
+use crate::cpu::Cpu;
+use crate::database::Profile;
+use crate::frontend::{InputEvent, InputSource, Renderer, SpriteViewerAction};
+use crate::logging;
+use crate::options::Options;
+use crate::overlay::Overlay;
+use crate::scheduler::Scheduler;
+use crate::watcher::FileWatcher;
+
+// Share of each frame's wall time spent emulating while fast-forwarding, the
+// rest is left for input and presenting.
+const FAST_FORWARD_BUDGET: f32 = 0.8;
+
+/*
+* How fast emulation is currently running.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    Normal,
+    Paused,
+    FastForward,
+    SlowMotion(u32), // Percent of normal speed
+}
+
+impl Speed {
+    /*
+    * The text shown by the on-screen indicator.
+    */
+    pub fn status(&self) -> String {
+        match self {
+            Speed::Normal => String::new(),
+            Speed::Paused => "PAUSED".to_string(),
+            Speed::FastForward => ">> FAST".to_string(),
+            Speed::SlowMotion(percent) => format!("> SLOW {}%", percent),
+        }
+    }
+}
+
+/*
+* Why emulation stopped.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Exit {
+    Quit,
+    Launcher, // The player wants to choose another ROM
+    Load(PathBuf), // Restart with this ROM, which may be the same one changed on disk
+}
+
+/*
+* Run the emulator until the user quits or asks for the launcher, drawing with
+* any Renderer and reading keys from any InputSource.
+*/
+pub fn run(cpu: &mut Cpu, renderer: &mut dyn Renderer, input: &mut dyn InputSource, options: &Options, profile: &Profile, rom_path: &Path) -> Exit {
+    let mut scheduler = Scheduler::new(FRAME_RATE);
+    let mut filter = options.phosphor_filter();
+    let mut overlay = Overlay::new(options.overlay, &profile.name);
+    let mut overlay_lines: Vec<String> = Vec::new();
+    let rom_name = rom_path
+        .file_name()
+        .map_or(rom_path.to_string_lossy(), |name| name.to_string_lossy());
+    overlay.notify(&format!("Loaded {}", rom_name));
+    renderer.set_background(profile.palette.background());
+    let mut watcher = options.watch.then(|| FileWatcher::new(archive::file_of(rom_path)));
+
+    let mut paused = false;
+    let mut fast_forward = false;
+    let mut slow_motion: Option<u32> = None;
+    let mut speed = Speed::Normal;
+
+    loop {
+        let mut step_frame = false;
+        let mut frames_run = 0;
+        let mut instructions_run = 0;
+
+        for event in input.poll_events() {
+            match event {
+                InputEvent::Quit => return Exit::Quit,
+                InputEvent::OpenLauncher => return Exit::Launcher,
+                InputEvent::LoadRom(path) => return Exit::Load(path),
+                InputEvent::Reset(soft) => {
+                    cpu.reset(soft);
+                    filter = options.phosphor_filter();
+                    overlay.notify(if soft { "Soft reset" } else { "Hard reset" });
+                },
+                InputEvent::KeyDown(key) => cpu.keyboard[key] = 1,
+                InputEvent::KeyUp(key) => cpu.keyboard[key] = 0,
+                InputEvent::ToggleFullscreen => renderer.toggle_fullscreen(),
+                InputEvent::Redraw => cpu.draw_flag = true,
+                InputEvent::TogglePause => {
+                    paused = !paused;
+                    scheduler.reset();
+                },
+                InputEvent::StepFrame => {
+                    paused = true;
+                    step_frame = true;
+                },
+                InputEvent::StepInstruction => {
+                    paused = true;
+                    cpu.cycle();
+                    instructions_run += 1;
+                },
+                InputEvent::FastForward(held) => {
+                    fast_forward = held;
+                    scheduler.reset();
+                },
+                InputEvent::CycleSlowMotion => {
+                    slow_motion = match slow_motion {
+                        None => Some(50),
+                        Some(50) => Some(25),
+                        _ => None,
+                    };
+                    scheduler.set_speed(slow_motion.unwrap_or(100));
+                    overlay.notify(&format!("Speed {}%", slow_motion.unwrap_or(100)));
+                },
+                InputEvent::ToggleOverlay => overlay.enabled = !overlay.enabled,
+                InputEvent::ToggleDebugger => renderer.toggle_debugger(),
+                InputEvent::ScrollDebugger(rows) => renderer.scroll_debugger(rows),
+                InputEvent::ToggleSpriteViewer => renderer.toggle_sprite_viewer(),
+                InputEvent::SpriteViewer(action) => renderer.sprite_viewer(action),
+                InputEvent::CloseWindow(window_id) => renderer.close_window(window_id),
+                InputEvent::ScrollWindow(window_id, rows) => renderer.scroll_window(window_id, rows),
+                InputEvent::Navigate(rows) => renderer.sprite_viewer(SpriteViewerAction::Scroll(rows)),
+                InputEvent::Select => {},
+            }
+        }
+
+        if watcher.as_mut().is_some_and(|watcher| watcher.changed()) {
+            return Exit::Load(rom_path.to_path_buf());
+        }
+
+        let new_speed = if paused {
+            Speed::Paused
+        } else if fast_forward {
+            Speed::FastForward
+        } else {
+            slow_motion.map_or(Speed::Normal, Speed::SlowMotion)
+        };
+        if new_speed != speed {
+            speed = new_speed;
+            overlay.set_status(&speed.status());
+        }
+
+        let frames_due = scheduler.wait();
+        match speed {
+            Speed::Paused => {
+                if step_frame {
+                    run_frame(cpu, profile.cycles_per_frame);
+                    frames_run += 1;
+                }
+            },
+            Speed::FastForward => {
+                // Emulate as many frames as fit in the frame's time budget
+                let start = Instant::now();
+                let budget = scheduler.frame_duration().mul_f32(FAST_FORWARD_BUDGET);
+                while start.elapsed() < budget {
+                    run_frame(cpu, profile.cycles_per_frame);
+                    frames_run += 1;
+                }
+            },
+            Speed::Normal | Speed::SlowMotion(_) => {
+                for _ in 0..frames_due {
+                    run_frame(cpu, profile.cycles_per_frame);
+                }
+                frames_run += frames_due;
+            },
+        }
+        instructions_run += frames_run as u64 * profile.cycles_per_frame as u64;
+
+        overlay.record(frames_run, instructions_run);
+        let lines = overlay.lines();
+        if lines != overlay_lines {
+            renderer.show_overlay(&lines);
+            overlay_lines = lines;
+            cpu.draw_flag = true;
+        }
+
+        // Present once per frame, and keep presenting while pixels fade out
+        let fading = filter.as_ref().is_some_and(|filter| filter.is_fading());
+        if cpu.draw_flag || fading {
+            let mut frame = profile.palette.apply(&cpu.display);
+            if let Some(filter) = filter.as_mut() {
+                frame = filter.apply(&frame, profile.palette.background());
+            }
+            renderer.redraw(&frame, VIDEO_WIDTH as usize, VIDEO_HEIGHT as usize);
+            cpu.draw_flag = false;
+        }
+        renderer.update_debugger(cpu);
+    }
+}
+
+/*
+* Set up a new CPU for a ROM with the seed, checks and counters the options
+* ask for. Call before loading the ROM.
+*/
+pub fn prepare(cpu: &mut Cpu, options: &Options) {
+    if let Some(seed) = options.seed {
+        cpu.seed_rng(seed);
+    }
+    if options.strict {
+        cpu.enable_strict();
+    }
+    if options.coverage_path.is_some() {
+        cpu.enable_coverage();
+    }
+    if options.profile_path.is_some() {
+        cpu.enable_profiler();
+    }
+}
+
+/*
+* Once a ROM stops, log the strict report and write the coverage and profile.
+* Coverage goes in lcov's format to a file ending in .info or .lcov and as an
+* annotated listing otherwise, and the profile as folded stacks to a file
+* ending in .folded and as the report otherwise.
+*/
+pub fn finish(cpu: &Cpu, options: &Options, rom_path: &str) {
+    if let Some(report) = cpu.strict_report() {
+        report.log();
+    }
+    if let (Some(path), Some(coverage)) = (&options.coverage_path, cpu.coverage()) {
+        let report = match extension(path).as_deref() {
+            Some("info") | Some("lcov") => coverage.lcov(cpu.rom(), rom_path),
+            _ => coverage.listing(cpu.rom(), cpu.memory()),
+        };
+        save("coverage", path, rom_path, report);
+    }
+    if let (Some(path), Some(profiler)) = (&options.profile_path, cpu.profiler()) {
+        let report = match extension(path).as_deref() {
+            Some("folded") => profiler.folded(),
+            _ => profiler.report(),
+        };
+        save("profile", path, rom_path, report);
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+fn save(what: &str, path: &str, rom_path: &str, report: String) {
+    match fs::write(path, report) {
+        Ok(()) => info!(target: logging::CPU, "Wrote the {} of {} to {}", what, rom_path, path),
+        Err(error) => error!(target: logging::CPU, "Cannot write {} to {}: {}", what, path, error),
+    }
+}
+
+/*
+* Emulate one 60 Hz frame, a fixed number of instructions then a timer tick.
+*/
+pub fn run_frame(cpu: &mut Cpu, cycles_per_frame: u32) {
+    for _ in 0..cycles_per_frame {
+        cpu.cycle();
+    }
+    cpu.tick_timers();
+}
@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+use crate::constants::*;
+use crate::palette::Rgb;
+
+/*
+* Writes the framebuffer as an uncompressed YUV4MPEG2 (Y4M) stream.
+* Each call to write_frame appends one 4:4:4 frame, so the output can be fed
+* straight into ffmpeg or any other encoder that understands raw Y4M.
+*/
+pub struct Y4mWriter {
+    writer: BufWriter<File>,
+    width: usize,
+    height: usize,
+}
+
+impl Y4mWriter {
+    pub fn new(file_name: &str, width: usize, height: usize) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, FRAME_RATE)?;
+
+        Ok(Y4mWriter {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    /*
+    * Append a frame, one colour per pixel in row order.
+    */
+    pub fn write_frame(&mut self, frame: &[Rgb]) -> std::io::Result<()> {
+        let pixel_count = self.width * self.height;
+        let mut planes = vec![0; pixel_count * 3];
+
+        for (i, pixel) in frame.iter().take(pixel_count).enumerate() {
+            let (y, u, v) = rgb_to_yuv(*pixel);
+            planes[i] = y;
+            planes[pixel_count + i] = u;
+            planes[pixel_count * 2 + i] = v;
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/*
+* Convert an RGB colour to full range BT.601 YUV.
+*/
+fn rgb_to_yuv(color: Rgb) -> (u8, u8, u8) {
+    let (r, g, b) = (color.r as f32, color.g as f32, color.b as f32);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let u = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let v = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    (y.round().clamp(0.0, 255.0) as u8,
+     u.round().clamp(0.0, 255.0) as u8,
+     v.round().clamp(0.0, 255.0) as u8)
+}
+
+/*
+* Writes the beeper output as a mono 16-bit PCM WAV file.
+* The RIFF and data chunk sizes are unknown until the run ends, so they are
+* written as 0 and patched in finish.
+*/
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    samples_written: u32,
+    phase: u32,
+}
+
+impl WavWriter {
+    pub fn new(file_name: &str) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+
+        let block_align: u16 = 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // mono
+        writer.write_all(&AUDIO_SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(AUDIO_SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            samples_written: 0,
+            phase: 0,
+        })
+    }
+
+    /*
+    * Append one frame worth of audio, a square wave while the beeper is on and
+    * silence otherwise.
+    */
+    pub fn write_frame(&mut self, beeping: bool) -> std::io::Result<()> {
+        let samples_per_frame = AUDIO_SAMPLE_RATE / FRAME_RATE;
+        let half_period = AUDIO_SAMPLE_RATE / (BEEP_FREQUENCY * 2);
+
+        for _ in 0..samples_per_frame {
+            let sample: i16 = if !beeping {
+                0
+            } else if (self.phase / half_period).is_multiple_of(2) {
+                BEEP_AMPLITUDE
+            } else {
+                -BEEP_AMPLITUDE
+            };
+            // Keep the wave continuous across frames so there are no clicks
+            self.phase = if beeping { self.phase.wrapping_add(1) } else { 0 };
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples_written += samples_per_frame;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        let data_size = self.samples_written * 2;
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,93 @@
+use crate::palette::Rgb;
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+/*
+* How the phosphor filter carries the previous frame into the next one.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    Persistence, // Lit pixels show at once, cleared pixels fade out
+    Blend,       // Every pixel is a running average of the frames before it
+}
+
+impl FilterMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "persistence" => Some(FilterMode::Persistence),
+            "blend" => Some(FilterMode::Blend),
+            _ => None,
+        }
+    }
+}
+
+/*
+* Emulates the afterglow of a CRT phosphor to hide the flicker of XOR drawn
+* sprites. Applied to each coloured frame before it is presented.
+* decay is the fraction of the previous frame kept each frame, from 0 (no
+* filtering) towards 1 (long trails).
+*/
+pub struct PhosphorFilter {
+    mode: FilterMode,
+    decay: f32,
+    glow: Vec<[f32; 3]>,
+    settled: bool,
+}
+
+impl PhosphorFilter {
+    pub fn new(mode: FilterMode, decay: f32) -> Self {
+        PhosphorFilter {
+            mode,
+            decay: decay.clamp(0.0, 0.99),
+            glow: Vec::new(),
+            settled: true,
+        }
+    }
+
+    /*
+    * Whether the last frame still had pixels fading, in which case frames have
+    * to keep being presented even if the framebuffer did not change.
+    */
+    pub fn is_fading(&self) -> bool {
+        !self.settled
+    }
+
+    pub fn apply(&mut self, frame: &[Rgb], background: Rgb) -> Vec<Rgb> {
+        if self.glow.len() != frame.len() {
+            self.glow = frame.iter().map(|pixel| channels(*pixel)).collect();
+        }
+
+        let background = channels(background);
+        self.settled = true;
+
+        frame.iter().zip(self.glow.iter_mut()).map(|(pixel, glow)| {
+            let target = channels(*pixel);
+
+            *glow = match self.mode {
+                FilterMode::Persistence if target != background => target,
+                FilterMode::Persistence => mix(background, *glow, self.decay),
+                FilterMode::Blend => mix(target, *glow, self.decay),
+            };
+
+            // Snap once the difference is invisible so fading ends
+            if glow.iter().zip(target.iter()).all(|(a, b)| (a - b).abs() < 1.0) {
+                *glow = target;
+            } else {
+                self.settled = false;
+            }
+
+            Rgb::new(glow[0].round() as u8, glow[1].round() as u8, glow[2].round() as u8)
+        }).collect()
+    }
+}
+
+fn channels(color: Rgb) -> [f32; 3] {
+    [color.r as f32, color.g as f32, color.b as f32]
+}
+
+/*
+* Move towards target, keeping the given fraction of previous.
+*/
+fn mix(target: [f32; 3], previous: [f32; 3], keep: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| target[i] + (previous[i] - target[i]) * keep)
+}
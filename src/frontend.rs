@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+use crate::constants::KEY_COUNT;
+use crate::cpu::Cpu;
+use crate::palette::Rgb;
+
+/*
+* A display backend, anything that can present the CHIP-8 framebuffer.
+*/
+pub trait Renderer {
+    /*
+    * Present a frame of width x height pixels, one colour per pixel in row order.
+    */
+    fn redraw(&mut self, frame: &[Rgb], width: usize, height: usize);
+
+    /*
+    * The palette's background colour, for backends that tell lit pixels from
+    * unlit ones by it. Changes with each ROM's palette.
+    */
+    fn set_background(&mut self, _background: Rgb) {}
+
+    /*
+    * Switch between windowed and fullscreen, for backends that have a window.
+    */
+    fn toggle_fullscreen(&mut self) {}
+
+    /*
+    * Show lines of text such as the emulation speed on top of the frame,
+    * no lines hides the overlay. Takes effect on the next redraw.
+    */
+    fn show_overlay(&mut self, _lines: &[String]) {}
+
+    /*
+    * Open or close the debugger panels, for backends that have them.
+    */
+    fn toggle_debugger(&mut self) {}
+
+    /*
+    * Scroll the debugger's memory view by a number of rows.
+    */
+    fn scroll_debugger(&mut self, _rows: i32) {}
+
+    /*
+    * Refresh the debugger panels from the CPU, if they are open.
+    */
+    fn update_debugger(&mut self, _cpu: &Cpu) {}
+
+    /*
+    * Open or close the sprite viewer, for backends that have one.
+    */
+    fn toggle_sprite_viewer(&mut self) {}
+
+    /*
+    * Move or reinterpret the sprite viewer's grid.
+    */
+    fn sprite_viewer(&mut self, _action: SpriteViewerAction) {}
+
+    /*
+    * Close one of the backend's secondary windows, e.g. the debugger.
+    */
+    fn close_window(&mut self, _window_id: u32) {}
+
+    /*
+    * Scroll whatever one of the backend's secondary windows shows.
+    */
+    fn scroll_window(&mut self, _window_id: u32, _rows: i32) {}
+}
+
+/*
+* Something the user did, translated from the backend's own events.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    Quit,
+    KeyDown(usize), // CHIP-8 key 0x0-0xF
+    KeyUp(usize),
+    ToggleFullscreen,
+    Redraw, // The frame was lost, e.g. the window was resized
+    TogglePause,
+    StepFrame,       // Pause and run exactly one frame
+    StepInstruction, // Pause and run exactly one instruction
+    FastForward(bool), // Run uncapped while held
+    CycleSlowMotion, // Normal -> 50% -> 25% -> normal
+    ToggleOverlay,
+    ToggleDebugger,
+    ScrollDebugger(i32), // Rows, negative scrolls up
+    ToggleSpriteViewer,
+    SpriteViewer(SpriteViewerAction),
+    CloseWindow(u32), // A secondary window by the backend's id
+    ScrollWindow(u32, i32),
+    Navigate(i32), // Up or down a menu or view, negative is up
+    Select,
+    OpenLauncher, // Stop the game and choose another ROM
+    LoadRom(PathBuf), // A file was dropped on the window
+    Reset(bool), // Restart the ROM, true for a soft reset that keeps memory
+}
+
+/*
+* Ways to change what the sprite viewer shows.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpriteViewerAction {
+    Scroll(i32), // Rows of the grid, negative scrolls up
+    Height(i32), // Change the height of 8 pixel wide sprites
+    ToggleLarge, // Switch between 8xN and 16x16 sprites
+    JumpToIndex, // Show the sprites starting at I
+    JumpToFont,  // Show the built-in font
+}
+
+/*
+* An input backend, anything that can report key presses to the emulator.
+*/
+pub trait InputSource {
+    /*
+    * Return the events that happened since the last poll, without blocking.
+    */
+    fn poll_events(&mut self) -> Vec<InputEvent>;
+
+    /*
+    * Bind the arrow keys and action buttons to the CHIP-8 keys a game uses.
+    */
+    fn set_game_keys(&mut self, _keys: &GameKeys) {}
+}
+
+/*
+* The CHIP-8 keys a game uses for directions and actions, from the ROM
+* database. Frontends bind them to the arrow keys, space (a) and shift (b),
+* in addition to the usual keypad layout.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GameKeys {
+    pub up: Option<usize>,
+    pub down: Option<usize>,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+}
+
+/*
+* The host key bound to each CHIP-8 key, indexed by the CHIP-8 key value.
+*
+*   1 2 3 4        1 2 3 C
+*   Q W E R   ->   4 5 6 D
+*   A S D F        7 8 9 E
+*   Z X C V        A 0 B F
+*/
+pub const KEY_LAYOUT: [char; KEY_COUNT] = [
+    'X', '1', '2', '3',
+    'Q', 'W', 'E', 'A',
+    'S', 'D', 'Z', 'C',
+    '4', 'R', 'F', 'V',
+];
+
+/*
+* Which host key plays each CHIP-8 key, KEY_LAYOUT unless the player picks
+* their own.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keymap {
+    keys: [char; KEY_COUNT], // Indexed by the CHIP-8 key value
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap { keys: KEY_LAYOUT }
+    }
+}
+
+impl Keymap {
+    /*
+    * Sixteen distinct characters, the host keys for CHIP-8 keys 0 to F in
+    * order, e.g. "X123QWEASDZC4RFV" for the default layout.
+    */
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let chars: Vec<char> = spec.chars().map(|c| c.to_ascii_uppercase()).collect();
+        let keys: [char; KEY_COUNT] = chars.try_into()
+            .map_err(|_| format!("A keymap needs {} keys, one for each of 0 to F, not {:?}", KEY_COUNT, spec))?;
+        if let Some(duplicate) = keys.iter().find(|&&key| keys.iter().filter(|&&other| other == key).count() > 1) {
+            return Err(format!("Key {:?} appears more than once in keymap {:?}", duplicate, spec));
+        }
+        Ok(Keymap { keys })
+    }
+
+    /*
+    * Look up the CHIP-8 key bound to a host key.
+    */
+    pub fn key_for_char(&self, c: char) -> Option<usize> {
+        self.keys.iter().position(|&key| key == c.to_ascii_uppercase())
+    }
+}
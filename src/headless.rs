@@ -0,0 +1,51 @@
+use log::info;
+use crate::constants::*;
+use crate::cpu::Cpu;
+use crate::database::Profile;
+use crate::emulator;
+use crate::export::{WavWriter, Y4mWriter};
+use crate::logging;
+use crate::options::Options;
+
+/*
+* Run the emulator without a window for a fixed number of frames.
+* Nothing waits on the wall clock, so long runs render faster than real time.
+* Every frame can be exported as Y4M video and the beeper as WAV audio.
+*/
+pub fn run(cpu: &mut Cpu, options: &Options, profile: &Profile, frames: u32) -> std::io::Result<()> {
+    let mut y4m = match &options.y4m_path {
+        Some(path) => Some(Y4mWriter::new(path, VIDEO_WIDTH as usize, VIDEO_HEIGHT as usize)?),
+        None => None,
+    };
+    let mut wav = match &options.wav_path {
+        Some(path) => Some(WavWriter::new(path)?),
+        None => None,
+    };
+
+    let mut filter = options.phosphor_filter();
+
+    for _ in 0..frames {
+        emulator::run_frame(cpu, profile.cycles_per_frame);
+
+        if let Some(y4m) = y4m.as_mut() {
+            let mut frame = profile.palette.apply(&cpu.display);
+            if let Some(filter) = filter.as_mut() {
+                frame = filter.apply(&frame, profile.palette.background());
+            }
+            y4m.write_frame(&frame)?;
+        }
+        if let Some(wav) = wav.as_mut() {
+            wav.write_frame(cpu.sound_timer > 0)?;
+        }
+    }
+
+    if let Some(y4m) = y4m {
+        y4m.finish()?;
+    }
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+
+    info!(target: logging::DISPLAY, "Ran {} frames headless", frames);
+    Ok(())
+}
@@ -0,0 +1,275 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{error, warn};
+use crate::archive;
+use crate::cartridge::is_rom;
+use crate::constants::*;
+use crate::cpu::Cpu;
+use crate::database::{Database, Profile};
+use crate::emulator::{self, Exit};
+use crate::frontend::{GameKeys, InputEvent, InputSource, Renderer};
+use crate::options::Options;
+
+const MAX_RECENT: usize = 8;
+const RECENT_FILE: &str = ".chip8_recent"; // In the home directory, one path per line
+const VISIBLE_ROWS: usize = 16; // Menu rows shown at once, headings included
+const MAX_NAME_LENGTH: usize = 40;
+const POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/*
+* Play ROMs until the user quits. Starts with the ROM given on the command
+* line, or the launcher menu without one, and returns to the menu whenever
+* the player asks to switch games.
+*/
+pub fn run(renderer: &mut dyn Renderer, input: &mut dyn InputSource, options: &Options) {
+    let mut launcher = Launcher::new(&options.rom_dir);
+    let database = Database::load();
+    let mut next_rom = options.rom_path.as_ref().map(PathBuf::from);
+    let mut reported: Option<PathBuf> = None; // The ROM the coverage and profile files were last written for
+
+    if options.debugger {
+        renderer.toggle_debugger();
+    }
+
+    loop {
+        let rom_path = match next_rom.take().or_else(|| launcher.choose(renderer, input, options)) {
+            Some(rom_path) => rom_path,
+            None => return,
+        };
+
+        let mut cpu = Cpu::new();
+        emulator::prepare(&mut cpu, options);
+        let cartridge = match cpu.load_rom(rom_path.to_string_lossy().to_string()) {
+            Ok(cartridge) => cartridge,
+            Err(message) => {
+                error!("{}", message);
+                launcher.set_message(&message);
+                continue;
+            },
+        };
+        launcher.add_recent(&rom_path);
+        let profile = Profile::for_rom(options, &database, cpu.rom_hash(), cartridge.as_ref());
+        cpu.set_quirks(profile.quirks);
+        input.set_game_keys(&profile.keys);
+
+        let exit = emulator::run(&mut cpu, renderer, input, options, &profile, &rom_path);
+        // Every ROM writes its reports to the same files
+        if let Some(previous) = reported.replace(rom_path.clone()).filter(|previous| *previous != rom_path) {
+            for path in options.coverage_path.iter().chain(&options.profile_path) {
+                warn!("Overwriting {}, which held the report of {}", path, previous.display());
+            }
+        }
+        emulator::finish(&cpu, options, &rom_path.to_string_lossy());
+        match exit {
+            Exit::Quit => return,
+            Exit::Launcher => {},
+            Exit::Load(path) => next_rom = Some(path),
+        }
+    }
+}
+
+/*
+* A menu of the ROMs in a directory, with the recently played ones on top.
+*/
+pub struct Launcher {
+    directory: PathBuf,
+    recent: Vec<PathBuf>, // Most recent first
+    roms: Vec<PathBuf>,
+    selected: usize, // Index into recent followed by roms
+    message: Option<String>, // Why the last ROM could not be played
+}
+
+impl Launcher {
+    pub fn new(directory: &str) -> Self {
+        let recent = recent_file()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+            .unwrap_or_default();
+
+        Launcher {
+            directory: PathBuf::from(directory),
+            recent,
+            roms: Vec::new(),
+            selected: 0,
+            message: None,
+        }
+    }
+
+    /*
+    * Read the ROM directory again, it may have changed since the menu was last open.
+    */
+    fn scan(&mut self) {
+        self.recent.retain(|path| archive::file_of(path).is_file());
+        self.roms = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file())
+                .flat_map(|path| roms_in(&path))
+                .collect(),
+            Err(error) => {
+                warn!("Cannot read ROM directory {}: {}", self.directory.display(), error);
+                Vec::new()
+            },
+        };
+        self.roms.sort();
+        self.selected = self.selected.min(self.entry_count().saturating_sub(1));
+    }
+
+    fn entry_count(&self) -> usize {
+        self.recent.len() + self.roms.len()
+    }
+
+    fn entry(&self, index: usize) -> Option<&PathBuf> {
+        self.recent.iter().chain(self.roms.iter()).nth(index)
+    }
+
+    /*
+    * Move a ROM to the top of the recently played list and save the list.
+    */
+    pub fn add_recent(&mut self, rom_path: &Path) {
+        let rom_path = match archive::split(rom_path) {
+            Some((archive_path, Some(inner))) => archive::join(&canonical(&archive_path), &inner),
+            _ => canonical(rom_path),
+        };
+        self.recent.retain(|path| *path != rom_path);
+        self.recent.insert(0, rom_path);
+        self.recent.truncate(MAX_RECENT);
+        self.selected = 0;
+
+        if let Some(path) = recent_file() {
+            let text: String = self.recent.iter().map(|path| format!("{}\n", path.display())).collect();
+            if let Err(error) = fs::write(&path, text) {
+                warn!("Cannot save recent ROMs to {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    /*
+    * Show a message such as an error above the menu until a ROM is chosen.
+    */
+    pub fn set_message(&mut self, message: &str) {
+        self.message = Some(message.to_string());
+    }
+
+    /*
+    * The menu as lines of text, scrolled so the selected ROM is visible.
+    */
+    fn lines(&self) -> Vec<String> {
+        // Every row of the menu, and the entry it selects if it is not a heading
+        let mut rows: Vec<(String, Option<usize>)> = Vec::new();
+        if !self.recent.is_empty() {
+            rows.push(("RECENT".to_string(), None));
+            rows.extend(self.recent.iter().enumerate().map(|(i, path)| (display_name(path), Some(i))));
+        }
+        rows.push((format!("ROMS IN {}", self.directory.display()), None));
+        if self.roms.is_empty() {
+            rows.push(("  NONE FOUND".to_string(), None));
+        }
+        rows.extend(self.roms.iter().enumerate().map(|(i, path)| (display_name(path), Some(self.recent.len() + i))));
+
+        let selected_row = rows.iter().position(|(_, entry)| *entry == Some(self.selected)).unwrap_or(0);
+        let first = selected_row.saturating_sub(VISIBLE_ROWS / 2).min(rows.len().saturating_sub(VISIBLE_ROWS));
+
+        let mut lines = vec!["CHIP-8 LAUNCHER".to_string()];
+        lines.extend(self.message.clone());
+        lines.extend(rows.iter().skip(first).take(VISIBLE_ROWS).map(|(text, entry)| match entry {
+            Some(index) if *index == self.selected => format!("> {}", text),
+            Some(_) => format!("  {}", text),
+            None => text.clone(),
+        }));
+        lines.push("UP/DOWN CHOOSE  ENTER PLAY  ESC QUIT".to_string());
+        lines
+    }
+
+    /*
+    * Show the menu until the player picks a ROM, None if they quit instead.
+    */
+    pub fn choose(&mut self, renderer: &mut dyn Renderer, input: &mut dyn InputSource, options: &Options) -> Option<PathBuf> {
+        self.scan();
+        input.set_game_keys(&GameKeys::default());
+        let palette = options.palette.unwrap_or_default();
+        renderer.set_background(palette.background());
+        let blank = palette.apply(&[0; VIDEO_WIDTH as usize * VIDEO_HEIGHT as usize]);
+        let mut dirty = true;
+
+        loop {
+            for event in input.poll_events() {
+                match event {
+                    InputEvent::Quit => return None,
+                    InputEvent::Navigate(rows) => {
+                        let last = self.entry_count().saturating_sub(1) as i32;
+                        self.selected = (self.selected as i32 + rows).clamp(0, last) as usize;
+                        dirty = true;
+                    },
+                    InputEvent::Select => {
+                        if let Some(rom_path) = self.entry(self.selected).cloned() {
+                            self.message = None;
+                            return Some(rom_path);
+                        }
+                    },
+                    InputEvent::LoadRom(rom_path) => {
+                        self.message = None;
+                        return Some(rom_path);
+                    },
+                    InputEvent::ToggleFullscreen => renderer.toggle_fullscreen(),
+                    InputEvent::CloseWindow(window_id) => renderer.close_window(window_id),
+                    InputEvent::Redraw => dirty = true,
+                    _ => {},
+                }
+            }
+
+            if dirty {
+                renderer.show_overlay(&self.lines());
+                renderer.redraw(&blank, VIDEO_WIDTH as usize, VIDEO_HEIGHT as usize);
+                dirty = false;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/*
+* The menu entries for a file in the ROM directory: the file itself if it is a
+* ROM, or one entry per ROM in a zip archive.
+*/
+fn roms_in(path: &Path) -> Vec<PathBuf> {
+    if archive::split(path).is_none() {
+        return if is_rom(path) { vec![path.to_path_buf()] } else { Vec::new() };
+    }
+    match archive::list(path, is_rom) {
+        Ok(names) => names.iter().map(|name| archive::join(path, name)).collect(),
+        Err(error) => {
+            warn!("{}", error);
+            Vec::new()
+        },
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or(path.to_string_lossy(), |name| name.to_string_lossy()).to_string()
+}
+
+/*
+* A file name short enough to fit the menu. ROMs in an archive show the
+* archive's name too.
+*/
+fn display_name(path: &Path) -> String {
+    let name = match archive::split(path) {
+        Some((archive_path, Some(inner))) => format!("{}: {}", file_name(&archive_path), file_name(Path::new(&inner))),
+        _ => file_name(path),
+    };
+    if name.chars().count() > MAX_NAME_LENGTH {
+        format!("{}...", name.chars().take(MAX_NAME_LENGTH - 3).collect::<String>())
+    } else {
+        name
+    }
+}
+
+fn recent_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(RECENT_FILE))
+}
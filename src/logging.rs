@@ -0,0 +1,50 @@
+use std::fs::File;
+use env_logger::{Target, WriteStyle};
+use log::LevelFilter;
+use crate::options::{Command, Options};
+
+/*
+* Log targets, one per subsystem, so each can be given its own level with
+* --log or RUST_LOG, e.g. "warn,cpu=trace".
+*/
+pub const CPU: &str = "cpu"; // Instructions executed and suspicious ROM behaviour
+pub const DISPLAY: &str = "display"; // Windows and presenting frames
+pub const INPUT: &str = "input"; // Keys and window events
+pub const TIMERS: &str = "timers"; // Delay and sound timers and frame pacing
+
+/*
+* Start logging. Info and above is shown unless RUST_LOG says otherwise, and
+* --log wins over both. Logs go to --log-file if given, otherwise to stdout,
+* or to stderr when stdout is taken by a command's output. The terminal
+* display shares its screen with both, so while it plays there are no logs
+* without --log-file.
+*/
+pub fn init(options: &Options) -> Result<(), String> {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Info);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    if let Some(filters) = &options.log_filter {
+        builder.parse_filters(filters);
+    }
+
+    match &options.log_file {
+        Some(path) => {
+            let file = File::create(path).map_err(|error| format!("Cannot create log file {}: {}", path, error))?;
+            builder.target(Target::Pipe(Box::new(file)));
+            builder.write_style(WriteStyle::Never);
+        },
+        None if options.terminal_mode.is_some() && options.command == Command::Run && options.headless_frames.is_none() => {
+            return Ok(());
+        },
+        None if options.terminal_mode.is_some() || options.command != Command::Run => {
+            builder.target(Target::Stderr);
+        },
+        None => {
+            builder.target(Target::Stdout);
+        },
+    }
+
+    builder.try_init().map_err(|error| error.to_string())
+}
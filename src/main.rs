@@ -1,104 +1,99 @@
 use std::env;
-use env_logger;
-use log::{debug, error};
-use std::time::{Duration, Instant};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use std::process;
 
+mod analysis;
+mod archive;
+mod cartridge;
+mod commands;
 mod cpu;
 mod constants;
+mod coverage;
+mod database;
+mod debugger;
+mod disasm;
 mod display;
+mod emulator;
+mod export;
+mod filter;
+mod frontend;
+mod headless;
+mod launcher;
+mod logging;
+mod options;
+mod overlay;
+mod octo;
+mod palette;
+mod profiler;
+mod quirks;
+mod scheduler;
+mod sprite_viewer;
+mod strict;
+mod terminal;
+mod watcher;
 
-fn main() {
-    let _ = env_logger::builder()
-            .target(env_logger::Target::Stdout)
-            .filter_level(log::LevelFilter::Info)
-            .is_test(false)
-            .try_init();
+use options::Command;
 
+fn main() {
     let args: Vec<String> = env::args().collect();
+    let options = options::Options::from_args(&args).unwrap_or_else(|message| {
+        eprintln!("error: {}\n\nRun '{} --help' for usage.", message, args[0]);
+        process::exit(2);
+    });
 
-    if args.len() != 2 {
-        panic!("Usage: {} <ROM>\n", args[0]);
+    if let Err(message) = logging::init(&options) {
+        eprintln!("error: {}", message);
+        process::exit(1);
     }
-    let rom_path: String = args[1].clone();
-
-    let mut display = display::Display::new(&constants::APPLICATION_TITLE.to_string(),
-                                                     constants::VIDEO_WIDTH as u32,
-                                                     constants::VIDEO_HEIGHT as u32);
-
-    let mut cpu = cpu::Cpu::new();
-    cpu.load_rom(rom_path);
-
-    let mut quit: bool = false;
-
-    let mut cycle_time = Instant::now();
-    let mut event_pump = display.sdl_context.event_pump().unwrap();
 
-    while ! quit {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    quit = true;
-                    break;
-                },
-                Event::KeyDown {
-                    keycode: Some(keycode),
-                    ..
-                } => {
-                   debug!("{:?}", keycode);
-                    // Reset keyboard
-                    for i in 0..16 {
-                        cpu.keyboard[i] = 0;
-                    }
-                    match keycode {
-                        Keycode::Num1 => cpu.keyboard[0x1] = 1,
-                        Keycode::Num2 => cpu.keyboard[0x2] = 1,
-                        Keycode::Num3 => cpu.keyboard[0x3] = 1,
-                        Keycode::Num4 => cpu.keyboard[0xC] = 1,
-                        Keycode::Q => cpu.keyboard[0x4] = 1,
-                        Keycode::W => cpu.keyboard[0x5] = 1,
-                        Keycode::E => cpu.keyboard[0x6] = 1,
-                        Keycode::R => cpu.keyboard[0xD] = 1,
-                        Keycode::A => cpu.keyboard[0x7] = 1,
-                        Keycode::S => cpu.keyboard[0x8] = 1,
-                        Keycode::D => cpu.keyboard[0x9] = 1,
-                        Keycode::F => cpu.keyboard[0xE] = 1,
-                        Keycode::Z => cpu.keyboard[0xA] = 1,
-                        Keycode::X => cpu.keyboard[0x0] = 1,
-                        Keycode::C => cpu.keyboard[0xB] = 1,
-                        Keycode::V => cpu.keyboard[0xF] = 1,
-                        _ => {}
-                    }
-                },
-                _ => error!("Unhandled event"),
-            }
-        }
-
-        if cpu.draw_flag {
-            display.redraw(&cpu.display);
-            cpu.draw_flag = false;
-        }
-
-       debug!("CPU {}", cpu);
-
-        cpu.cycle();
+    // Ok(false) when a test fails
+    let result = match options.command {
+        Command::Run => run(&options),
+        Command::Help => {
+            print!("{}", options::Options::help(&args[0]));
+            Ok(true)
+        },
+        Command::Disasm => commands::disasm(&options).map(|_| true),
+        Command::Asm => commands::asm(&options).map(|_| true),
+        Command::Info => commands::info(&options).map(|_| true),
+        Command::Analyze => commands::analyze(&options).map(|_| true),
+        Command::Test => commands::test(&options),
+    };
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            process::exit(1);
+        },
+    }
+}
 
-        if cycle_time.elapsed() >= Duration::from_millis(1000 / 60) {
-            if cpu.delay_timer > 0 {
-                cpu.delay_timer -= 1;
-            }
-            if cpu.sound_timer > 0 {
-                cpu.sound_timer -= 1;
-            }
-            cycle_time = Instant::now();
-        }
+fn run(options: &options::Options) -> Result<bool, String> {
+    if let Some(frames) = options.headless_frames {
+        let mut cpu = cpu::Cpu::new();
+        emulator::prepare(&mut cpu, options);
+        let cartridge = cpu.load_rom(options.rom_path.clone().unwrap())?;
+        let profile = database::Profile::for_rom(options, &database::Database::load(), cpu.rom_hash(), cartridge.as_ref());
+        cpu.set_quirks(profile.quirks);
+        headless::run(&mut cpu, options, &profile, frames).map_err(|error| format!("Cannot write the export: {}", error))?;
+        emulator::finish(&cpu, options, options.rom_path.as_deref().unwrap_or_default());
+        return Ok(true);
+    }
 
-        ::std::thread::sleep(Duration::from_micros(1000));
+    if let Some(mode) = options.terminal_mode {
+        let mut renderer = terminal::Terminal::new(mode, options.palette.unwrap_or_default().background());
+        let mut input = terminal::TerminalInput::new(options.keymap);
+        launcher::run(&mut renderer, &mut input, options);
+        return Ok(true);
     }
 
+    let mut display = display::Display::new(constants::APPLICATION_TITLE,
+                                                     constants::VIDEO_WIDTH as u32,
+                                                     constants::VIDEO_HEIGHT as u32,
+                                                     options.scale,
+                                                     options.integer_scale,
+                                                     options.vsync);
+    let mut input = display.input(options.keymap);
+    launcher::run(&mut display, &mut input, options);
+    Ok(true)
 }
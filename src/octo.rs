@@ -0,0 +1,1041 @@
+use std::collections::{HashMap, VecDeque};
+use crate::constants::*;
+
+/*
+* An assembler for Octo, the high level CHIP-8 assembly language used by the
+* Octo IDE and the programs inside Octo cartridges. Supports the instructions
+* of CHIP-8, SUPER-CHIP and XO-CHIP, labels, constants, aliases, if/then,
+* if/begin/else/end, loop/while/again, :next, :org, :byte, :pointer, :unpack,
+* :macro, :calc and :assert. :stringmode is not supported.
+*
+* The program starts with a jump to the label main, as in Octo.
+*/
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler::new(tokenize(source)?);
+    assembler.run()?;
+    Ok(assembler.rom)
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    depth: usize, // Macro expansions it came out of, 0 for source
+}
+
+/*
+* Split source into whitespace separated words. Comments run from # to the end
+* of the line, strings are kept whole with their quotes, and braces and
+* parentheses are always words of their own.
+*/
+fn tokenize(source: &str) -> Result<VecDeque<Token>, String> {
+    let mut tokens = VecDeque::new();
+
+    for (number, text) in source.lines().enumerate() {
+        let line = number + 1;
+        let mut chars = text.chars().peekable();
+        let mut word = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '#' => break,
+                '"' => {
+                    let mut string = String::from('"');
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => string.push(c),
+                            None => return Err(format!("line {}: unterminated string", line)),
+                        }
+                    }
+                    string.push('"');
+                    tokens.push_back(Token { text: string, line, depth: 0 });
+                },
+                '{' | '}' | '(' | ')' => {
+                    if !word.is_empty() {
+                        tokens.push_back(Token { text: std::mem::take(&mut word), line, depth: 0 });
+                    }
+                    tokens.push_back(Token { text: c.to_string(), line, depth: 0 });
+                },
+                c if c.is_whitespace() => {
+                    if !word.is_empty() {
+                        tokens.push_back(Token { text: std::mem::take(&mut word), line, depth: 0 });
+                    }
+                },
+                c => word.push(c),
+            }
+        }
+        if !word.is_empty() {
+            tokens.push_back(Token { text: word, line, depth: 0 });
+        }
+    }
+
+    Ok(tokens)
+}
+
+/*
+* A number literal: decimal, 0x hex or 0b binary, optionally negative.
+*/
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '-')
+        && parse_number(text).is_none()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fixup {
+    Address, // Low 12 bits of an instruction
+    Long, // A whole 16 bit word
+    HighNibble, // Low 4 bits of an instruction, bits 8-11 of the address
+    HighByte, // Low 8 bits of an instruction, the high byte of the address
+    LowByte, // Low 8 bits of an instruction, the low byte of the address
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+/*
+* One side of a comparison.
+*/
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+/*
+* A compiled condition: instructions to run first, then an instruction that
+* skips when the condition is true and one that skips when it is false.
+*/
+struct Condition {
+    prelude: Vec<u16>,
+    skip_if_true: u16,
+    skip_if_false: u16,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>, // Starting at ROM_START
+    here: usize, // Address the next byte is assembled at
+    line: usize, // Of the last token read, for errors
+    depth: usize, // Macro expansions the last token read came out of
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup, String, usize)>, // Address, kind, label and line
+    next_label: Option<String>, // From :next, set on the next instruction's second byte
+    branches: Vec<usize>, // Jumps waiting for else or end
+    loops: Vec<(usize, Vec<usize>)>, // Loop start and the jumps out of it from while
+}
+
+impl Assembler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        Assembler {
+            tokens,
+            rom: Vec::new(),
+            here: ROM_START as usize,
+            line: 0,
+            depth: 0,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            next_label: None,
+            branches: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line, message))
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                self.depth = token.depth;
+                Ok(token.text)
+            },
+            None => self.error("unexpected end of program"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(&format!("expected '{}', found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        // Reserve the jump to main
+        self.emit(0x0000);
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if let Some(&(start, _)) = self.loops.last() {
+            return Err(format!("loop at 0x{:03X} has no again", start));
+        }
+        if !self.branches.is_empty() {
+            return Err("begin without end".to_string());
+        }
+
+        let main = match self.labels.get("main") {
+            Some(&main) => main,
+            None => return Err("the program has no main".to_string()),
+        };
+        self.patch(ROM_START as usize, 0x1000 | main as u16);
+
+        for (address, kind, name, line) in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&name) {
+                Some(&target) => target,
+                None => return Err(format!("line {}: undefined name '{}'", line, name)),
+            };
+            let offset = address - ROM_START as usize;
+            match kind {
+                Fixup::Address => {
+                    if target > 0xFFF {
+                        return Err(format!("line {}: '{}' at 0x{:X} is out of reach", line, name, target));
+                    }
+                    self.rom[offset] |= (target >> 8) as u8;
+                    self.rom[offset + 1] = target as u8;
+                },
+                Fixup::Long => {
+                    self.rom[offset] = (target >> 8) as u8;
+                    self.rom[offset + 1] = target as u8;
+                },
+                Fixup::HighNibble => {
+                    if target > 0xFFF {
+                        return Err(format!("line {}: '{}' at 0x{:X} is out of reach", line, name, target));
+                    }
+                    self.rom[offset + 1] |= (target >> 8) as u8;
+                },
+                Fixup::HighByte => self.rom[offset + 1] = (target >> 8) as u8,
+                Fixup::LowByte => self.rom[offset + 1] = target as u8,
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let offset = self.here - ROM_START as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        if let Some(label) = self.next_label.take() {
+            self.labels.insert(label, self.here + 1);
+        }
+        let offset = self.here - ROM_START as usize;
+        if offset + 2 > self.rom.len() {
+            self.rom.resize(offset + 2, 0);
+        }
+        self.rom[offset] = (opcode >> 8) as u8;
+        self.rom[offset + 1] = opcode as u8;
+        self.here += 2;
+    }
+
+    fn patch(&mut self, address: usize, opcode: u16) {
+        let offset = address - ROM_START as usize;
+        self.rom[offset] = (opcode >> 8) as u8;
+        self.rom[offset + 1] = opcode as u8;
+    }
+
+    /*
+    * Emit an instruction with a 12 bit address, resolved later if it is a
+    * label not defined yet.
+    */
+    fn emit_address(&mut self, opcode: u16) -> Result<(), String> {
+        let token = self.next()?;
+        match self.constant(&token) {
+            Some(value) => {
+                if !(0..=0xFFF).contains(&value) {
+                    return self.error(&format!("address {} does not fit in 12 bits", token));
+                }
+                self.emit(opcode | value as u16);
+            },
+            None if is_identifier(&token) => {
+                self.fixups.push((self.here, Fixup::Address, token, self.line));
+                self.emit(opcode);
+            },
+            None => return self.error(&format!("expected an address, found '{}'", token)),
+        }
+        Ok(())
+    }
+
+    fn constant(&self, token: &str) -> Option<i64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).map(|value| *value as i64))
+            .or_else(|| self.labels.get(token).map(|address| *address as i64))
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        match self.register_of(&token) {
+            Some(register) => Ok(register),
+            None => self.error(&format!("expected a register, found '{}'", token)),
+        }
+    }
+
+    fn register_of(&self, token: &str) -> Option<u8> {
+        parse_register(token).or_else(|| self.aliases.get(token).copied())
+    }
+
+    fn byte_of(&self, token: &str) -> Result<u8, String> {
+        match self.constant(token) {
+            Some(value) if (-128..=255).contains(&value) => Ok(value as u8),
+            Some(_) => self.error(&format!("{} does not fit in a byte", token)),
+            None => self.error(&format!("expected a number, found '{}'", token)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.byte_of(&token)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        match self.constant(&token) {
+            Some(value) if (0..=0xF).contains(&value) => Ok(value as u16),
+            _ => self.error(&format!("expected a number from 0 to 15, found '{}'", token)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+
+        if let Some(register) = self.register_of(&token) {
+            return self.assignment(register as u16);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(name)?;
+            },
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = match self.constant(&value) {
+                    Some(value) => value as f64,
+                    None => return self.error(&format!("unknown constant '{}'", value)),
+                };
+                self.constants.insert(name, value);
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            },
+            // v0 := the nibble and the top 4 bits of a 12 bit address, or the
+            // high byte of a long one, v1 := the low byte
+            ":unpack" => {
+                let nibble = if self.peek() == Some("long") {
+                    self.next()?;
+                    None
+                } else {
+                    Some(self.nibble()?)
+                };
+                let label = self.next()?;
+                let top = nibble.map_or(0, |nibble| nibble << 4);
+                match self.constant(&label) {
+                    Some(address) => {
+                        let limit = if nibble.is_some() { 0xFFF } else { 0xFFFF };
+                        if !(0..=limit).contains(&address) {
+                            return self.error(&format!("cannot unpack address {}", label));
+                        }
+                        let address = address as u16;
+                        let high = if nibble.is_some() { address >> 8 & 0xF } else { address >> 8 };
+                        self.emit(0x6000 | top | high);
+                        self.emit(0x6100 | address & 0xFF);
+                    },
+                    None if is_identifier(&label) => {
+                        let kind = if nibble.is_some() { Fixup::HighNibble } else { Fixup::HighByte };
+                        self.fixups.push((self.here, kind, label.clone(), self.line));
+                        self.emit(0x6000 | top);
+                        self.fixups.push((self.here, Fixup::LowByte, label, self.line));
+                        self.emit(0x6100);
+                    },
+                    None => return self.error(&format!("expected an address, found '{}'", label)),
+                }
+            },
+            ":next" => {
+                let name = self.next()?;
+                self.next_label = Some(name);
+            },
+            ":org" => {
+                let token = self.next()?;
+                match self.constant(&token) {
+                    Some(address) if (ROM_START as i64..=0xFFFF).contains(&address) => self.here = address as usize,
+                    _ => return self.error(&format!("cannot :org to '{}'", token)),
+                }
+            },
+            ":byte" => {
+                let byte = if self.peek() == Some("{") {
+                    let value = self.calculation()? as i64;
+                    if !(-128..=255).contains(&value) {
+                        return self.error(&format!("{} does not fit in a byte", value));
+                    }
+                    value as u8
+                } else {
+                    let token = self.next()?;
+                    self.byte_of(&token)?
+                };
+                self.emit_byte(byte);
+            },
+            ":pointer" => {
+                let token = self.next()?;
+                match self.constant(&token) {
+                    Some(value) => self.emit(value as u16),
+                    None => {
+                        self.fixups.push((self.here, Fixup::Long, token, self.line));
+                        self.emit(0);
+                    },
+                }
+            },
+            ":call" => self.emit_address(0x2000)?,
+            ":macro" => self.define_macro()?,
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calculation()?;
+                self.constants.insert(name, value);
+            },
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(text) if text.starts_with('"') => self.next()?.trim_matches('"').to_string(),
+                    _ => "assertion failed".to_string(),
+                };
+                if self.calculation()? == 0.0 {
+                    return self.error(&message);
+                }
+            },
+            ":breakpoint" | ":proto" => {
+                self.next()?;
+            },
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            },
+            ":stringmode" => return self.error(":stringmode is not supported"),
+            ";" | "return" => self.emit(0x00EE),
+            "clear" => self.emit(0x00E0),
+            "hires" => self.emit(0x00FF),
+            "lores" => self.emit(0x00FE),
+            "exit" => self.emit(0x00FD),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            },
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            },
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "audio" => self.emit(0xF002),
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8);
+            },
+            "bcd" => {
+                let x = self.register()? as u16;
+                self.emit(0xF033 | x << 8);
+            },
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    self.emit(if token == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                } else {
+                    self.emit(if token == "save" { 0xF055 } else { 0xF065 } | x << 8);
+                }
+            },
+            "saveflags" => {
+                let x = self.register()? as u16;
+                self.emit(0xF075 | x << 8);
+            },
+            "loadflags" => {
+                let x = self.register()? as u16;
+                self.emit(0xF085 | x << 8);
+            },
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            },
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xB000)?,
+            "native" => self.emit_address(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                let low = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x << 8 | low);
+            },
+            "i" => self.index_assignment()?,
+            "if" => self.if_statement()?,
+            "else" => {
+                let branch = match self.branches.pop() {
+                    Some(branch) => branch,
+                    None => return self.error("else without if ... begin"),
+                };
+                let jump = self.here;
+                self.emit(0x1000);
+                self.patch(branch, 0x1000 | self.here as u16);
+                self.branches.push(jump);
+            },
+            "end" => {
+                match self.branches.pop() {
+                    Some(branch) => self.patch(branch, 0x1000 | self.here as u16),
+                    None => return self.error("end without if ... begin"),
+                }
+            },
+            "loop" => self.loops.push((self.here, Vec::new())),
+            "while" => {
+                let condition = self.condition()?;
+                if self.loops.is_empty() {
+                    return self.error("while outside of a loop");
+                }
+                for opcode in condition.prelude {
+                    self.emit(opcode);
+                }
+                self.emit(condition.skip_if_true);
+                let jump = self.here;
+                self.emit(0x1000);
+                if let Some((_, exits)) = self.loops.last_mut() {
+                    exits.push(jump);
+                }
+            },
+            "again" => {
+                let (start, exits) = match self.loops.pop() {
+                    Some(current) => current,
+                    None => return self.error("again without loop"),
+                };
+                self.emit(0x1000 | start as u16);
+                for exit in exits {
+                    self.patch(exit, 0x1000 | self.here as u16);
+                }
+            },
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            // Numbers and constants on their own are data bytes
+            _ if parse_number(&token).is_some() || self.constants.contains_key(&token) => {
+                let byte = self.byte_of(&token)?;
+                self.emit_byte(byte);
+            },
+            _ if is_identifier(&token) => {
+                // A bare name calls that subroutine
+                self.tokens.push_front(Token { text: token, line: self.line, depth: self.depth });
+                self.emit_address(0x2000)?;
+            },
+            _ => return self.error(&format!("unexpected '{}'", token)),
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return self.error(&format!("'{}' is already defined", name));
+        }
+        if parse_number(&name).is_some() || self.register_of(&name).is_some() {
+            return self.error(&format!("'{}' cannot be a label", name));
+        }
+        self.labels.insert(name, self.here);
+        Ok(())
+    }
+
+    /*
+    * vx := ..., vx += ... and the other register operations.
+    */
+    fn assignment(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let token = self.next()?;
+
+        if let Some(y) = self.register_of(&token) {
+            let y = y as u16;
+            let low = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.error(&format!("unknown operator '{}'", operator)),
+            };
+            self.emit(0x8000 | x << 8 | y << 4 | low);
+            return Ok(());
+        }
+
+        match (operator.as_str(), token.as_str()) {
+            (":=", "key") => self.emit(0xF00A | x << 8),
+            (":=", "delay") => self.emit(0xF007 | x << 8),
+            (":=", "random") => {
+                let mask = self.byte()? as u16;
+                self.emit(0xC000 | x << 8 | mask);
+            },
+            (":=", _) => {
+                let value = self.byte_of(&token)? as u16;
+                self.emit(0x6000 | x << 8 | value);
+            },
+            ("+=", _) => {
+                let value = self.byte_of(&token)? as u16;
+                self.emit(0x7000 | x << 8 | value);
+            },
+            ("-=", _) => {
+                let value = self.byte_of(&token)?.wrapping_neg() as u16;
+                self.emit(0x7000 | x << 8 | value);
+            },
+            _ => return self.error(&format!("cannot use '{}' with '{}'", operator, token)),
+        }
+        Ok(())
+    }
+
+    fn index_assignment(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        match operator.as_str() {
+            "+=" => {
+                let x = self.register()? as u16;
+                self.emit(0xF01E | x << 8);
+            },
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()? as u16;
+                    self.emit(0xF029 | x << 8);
+                },
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()? as u16;
+                    self.emit(0xF030 | x << 8);
+                },
+                Some("long") => {
+                    self.next()?;
+                    self.emit(0xF000);
+                    let token = self.next()?;
+                    match self.constant(&token) {
+                        Some(value) => self.emit(value as u16),
+                        None => {
+                            self.fixups.push((self.here, Fixup::Long, token, self.line));
+                            self.emit(0);
+                        },
+                    }
+                },
+                _ => self.emit_address(0xA000)?,
+            },
+            _ => return self.error(&format!("unknown operator 'i {}'", operator)),
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result<(), String> {
+        let condition = self.condition()?;
+        for opcode in &condition.prelude {
+            self.emit(*opcode);
+        }
+
+        match self.next()?.as_str() {
+            // The next statement runs when the condition is true
+            "then" => self.emit(condition.skip_if_false),
+            "begin" => {
+                self.emit(condition.skip_if_true);
+                self.branches.push(self.here);
+                self.emit(0x1000);
+            },
+            other => return self.error(&format!("expected then or begin, found '{}'", other)),
+        }
+        Ok(())
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        let token = self.next()?;
+        match self.register_of(&token) {
+            Some(register) => Ok(Operand::Register(register)),
+            None => Ok(Operand::Byte(self.byte_of(&token)?)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.register()? as u16;
+        let operator = self.next()?;
+
+        match operator.as_str() {
+            "key" => return Ok(Condition { prelude: Vec::new(), skip_if_true: 0xE09E | x << 8, skip_if_false: 0xE0A1 | x << 8 }),
+            "-key" => return Ok(Condition { prelude: Vec::new(), skip_if_true: 0xE0A1 | x << 8, skip_if_false: 0xE09E | x << 8 }),
+            _ => {},
+        }
+
+        let operand = self.operand()?;
+        let (equal, not_equal) = match operand {
+            Operand::Register(y) => (0x5000 | x << 8 | (y as u16) << 4, 0x9000 | x << 8 | (y as u16) << 4),
+            Operand::Byte(value) => (0x3000 | x << 8 | value as u16, 0x4000 | x << 8 | value as u16),
+        };
+
+        match operator.as_str() {
+            "==" => Ok(Condition { prelude: Vec::new(), skip_if_true: equal, skip_if_false: not_equal }),
+            "!=" => Ok(Condition { prelude: Vec::new(), skip_if_true: not_equal, skip_if_false: equal }),
+            "<" | ">=" | ">" | "<=" => {
+                // Compare with a subtraction in VF, its flag is 1 when the
+                // left side is greater than or equal to the right
+                let left = Operand::Register(x as u8);
+                let (a, b) = if operator == "<" || operator == ">=" { (left, operand) } else { (operand, left) };
+                let prelude = match (a, b) {
+                    (Operand::Register(a), Operand::Register(b)) => vec![0x8F00 | (a as u16) << 4, 0x8F05 | (b as u16) << 4],
+                    (Operand::Register(a), Operand::Byte(b)) => vec![0x6F00 | b as u16, 0x8F07 | (a as u16) << 4],
+                    (Operand::Byte(a), Operand::Register(b)) => vec![0x6F00 | a as u16, 0x8F05 | (b as u16) << 4],
+                    (Operand::Byte(_), Operand::Byte(_)) => unreachable!(),
+                };
+                // True when the flag is 0 for < and >, 1 for >= and <=
+                let flag = if operator == "<" || operator == ">" { 0 } else { 1 };
+                Ok(Condition { prelude, skip_if_true: 0x3F00 | flag, skip_if_false: 0x4F00 | flag })
+            },
+            _ => self.error(&format!("unknown comparison '{}'", operator)),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next()?;
+        let mut arguments = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            arguments.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return self.error(&format!("macro '{}' has no closing brace", name)),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => {},
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { arguments, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        let depth = self.depth + 1;
+        if depth > MAX_MACRO_DEPTH {
+            return self.error(&format!("macro '{}' expands more than {} levels deep, is it recursive?", name, MAX_MACRO_DEPTH));
+        }
+        let argument_count = self.macros[name].arguments.len();
+        let mut values = HashMap::new();
+        for i in 0..argument_count {
+            let value = self.next()?;
+            values.insert(self.macros[name].arguments[i].clone(), value);
+        }
+
+        let line = self.line;
+        let expansion: Vec<Token> = self.macros[name].body.iter()
+            .map(|token| Token {
+                text: values.get(&token.text).cloned().unwrap_or_else(|| token.text.clone()),
+                line,
+                depth,
+            })
+            .collect();
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /*
+    * Evaluate a { ... } expression. Like Octo, binary operators have no
+    * precedence and are evaluated right to left, use parentheses to group.
+    */
+    fn calculation(&mut self) -> Result<f64, String> {
+        self.expect("{")?;
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let left = self.term()?;
+        let operator = match self.peek() {
+            Some(token) if BINARY_OPERATORS.contains(&token) => self.next()?,
+            _ => return Ok(left),
+        };
+        let right = self.expression()?;
+
+        let (a, b) = (left as i64, right as i64);
+        if operator == "<<" || operator == ">>" {
+            let shifted = u32::try_from(b).ok()
+                .and_then(|b| if operator == "<<" { a.checked_shl(b) } else { a.checked_shr(b) });
+            return match shifted {
+                Some(value) => Ok(value as f64),
+                None => self.error(&format!("cannot shift {} by {}", a, b)),
+            };
+        }
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<" => truth(left < right),
+            ">" => truth(left > right),
+            "<=" => truth(left <= right),
+            ">=" => truth(left >= right),
+            "==" => truth(left == right),
+            "!=" => truth(left != right),
+            _ => unreachable!(),
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        if token == "(" {
+            let value = self.expression()?;
+            self.expect(")")?;
+            return Ok(value);
+        }
+
+        if UNARY_OPERATORS.contains(&token.as_str()) {
+            let value = self.term()?;
+            return Ok(match token.as_str() {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => if value == 0.0 { 1.0 } else { 0.0 },
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => value.signum(),
+                "ceil" => value.ceil(),
+                "floor" => value.floor(),
+                // The byte assembled at an address so far
+                "@" => {
+                    let offset = (value as usize).wrapping_sub(ROM_START as usize);
+                    *self.rom.get(offset).unwrap_or(&0) as f64
+                },
+                _ => unreachable!(),
+            });
+        }
+
+        match token.as_str() {
+            "HERE" => return Ok(self.here as f64),
+            "PI" => return Ok(std::f64::consts::PI),
+            "E" => return Ok(std::f64::consts::E),
+            _ => {},
+        }
+        if let Some(&value) = self.constants.get(&token) {
+            return Ok(value);
+        }
+        match self.constant(&token) {
+            Some(value) => Ok(value as f64),
+            None => self.error(&format!("unknown name '{}' in calculation", token)),
+        }
+    }
+}
+
+// Macros may use other macros this many levels deep
+const MAX_MACRO_DEPTH: usize = 64;
+
+const BINARY_OPERATORS: [&str; 19] = [
+    "+", "-", "*", "/", "%", "pow", "min", "max", "&", "|", "^", "<<", ">>",
+    "<", ">", "<=", ">=", "==", "!=",
+];
+
+const UNARY_OPERATORS: [&str; 14] = [
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor", "@",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The assembled program as big-endian words, the jump to main first
+    fn words(source: &str) -> Vec<u16> {
+        assemble(source).unwrap().chunks(2)
+            .map(|pair| (pair[0] as u16) << 8 | *pair.get(1).unwrap_or(&0) as u16)
+            .collect()
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = "
+            : main
+                loop
+                    v0 += 1
+                    while v0 != 5
+                again";
+        assert_eq!(words(source), [0x1202, 0x7001, 0x4005, 0x120A, 0x1202]);
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = "
+            : main
+                if v1 == 2 begin
+                    v2 := 1
+                else
+                    v2 := 2
+                end";
+        assert_eq!(words(source), [0x1202, 0x3102, 0x120A, 0x6201, 0x120C, 0x6202]);
+    }
+
+    #[test]
+    fn if_then() {
+        assert_eq!(words(": main if v0 == 1 then v1 := 3"), [0x1202, 0x4001, 0x6103]);
+    }
+
+    #[test]
+    fn next_labels_the_second_byte() {
+        let source = "
+            : main
+                :next operand v0 := 0
+                i := operand";
+        assert_eq!(words(source), [0x1202, 0x6000, 0xA203]);
+    }
+
+    #[test]
+    fn forward_calls() {
+        let source = "
+            : main
+                draw
+                :call draw
+                jump main
+            : draw
+                return";
+        assert_eq!(words(source), [0x1202, 0x2208, 0x2208, 0x1202, 0x00EE]);
+    }
+
+    #[test]
+    fn unpack_loads_an_address_into_v0_and_v1() {
+        let source = "
+            : main
+                :unpack 0xA data
+                :unpack long data
+            : data";
+        assert_eq!(words(source), [0x1202, 0x60A2, 0x610A, 0x6002, 0x610A]);
+        assert_eq!(assemble(": sprite 0xFF : main :unpack 1 sprite").unwrap(), [0x12, 0x03, 0xFF, 0x60, 0x12, 0x61, 0x02]);
+    }
+
+    #[test]
+    fn macros() {
+        let source = "
+            :macro add-to register amount { register += amount }
+            : main
+                add-to v3 4
+                add-to v4 5";
+        assert_eq!(words(source), [0x1202, 0x7304, 0x7405]);
+    }
+
+    #[test]
+    fn recursive_macros_are_errors() {
+        assert!(assemble(":macro forever { forever } : main forever").is_err());
+        assert!(assemble(":macro ping { pong } :macro pong { ping } : main ping").is_err());
+    }
+
+    #[test]
+    fn calc() {
+        // No precedence, right to left: 10 - ( 4 - 3 )
+        let source = "
+            :calc size { 2 * ( 3 + 4 ) }
+            :calc rest { 10 - 4 - 3 }
+            : main
+                v0 := size
+                v1 := rest
+                :byte { 1 << 4 }";
+        assert_eq!(words(source), [0x1202, 0x600E, 0x6109, 0x1000]);
+    }
+
+    #[test]
+    fn less_than_subtracts_into_vf() {
+        // VF := v1 - v2, which borrows when v1 < v2
+        assert_eq!(words(": main if v1 < v2 then v3 := 1"), [0x1202, 0x8F10, 0x8F25, 0x4F00, 0x6301]);
+    }
+
+    #[test]
+    fn greater_or_equal_subtracts_into_vf() {
+        // VF := v1 - 5, which does not borrow when v1 >= 5
+        assert_eq!(words(": main if v1 >= 5 then v3 := 2"), [0x1202, 0x6F05, 0x8F17, 0x4F01, 0x6302]);
+    }
+
+    #[test]
+    fn shifts_out_of_range_are_errors() {
+        assert!(assemble(":calc big { 1 << 70 } : main").is_err());
+        assert!(assemble(":calc small { 1 >> -1 } : main").is_err());
+    }
+
+    #[test]
+    fn bytes_out_of_range_are_errors() {
+        assert!(assemble(": main :byte { 300 }").is_err());
+        assert!(assemble(": main :byte 300").is_err());
+        assert_eq!(words(": main :byte { -1 }"), [0x1202, 0xFF00]);
+    }
+}
@@ -0,0 +1,344 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::display;
+use crate::filter::{self, FilterMode, PhosphorFilter};
+use crate::frontend::Keymap;
+use crate::palette::Palette;
+use crate::quirks::{Platform, QuirkOverrides};
+use crate::terminal::TerminalMode;
+
+/*
+* Command line options.
+*
+* Usage: <program> [run] [ROM] [OPTIONS]
+*        <program> disasm ROM [--output FILE]
+*        <program> asm SOURCE [--output FILE]
+*        <program> info ROM
+*        <program> analyze ROM [--output FILE] [--dot FILE]
+*        <program> test ROM [--frames N] [--expect FILE] [OPTIONS]
+*
+* Any option can also be set in a TOML config file, by its name without the
+* dashes, e.g. `palette = "amber"` or `vsync = true`. Options given on the
+* command line win.
+*/
+
+// Read when there is no --config, in the home directory.
+const CONFIG_FILE: &str = ".chip8.toml";
+
+const DEFAULT_TEST_FRAMES: u32 = 600; // Ten seconds, long enough for the common test ROMs
+
+/*
+* What the emulator was asked to do.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Run, // Play ROMs, or export them with --headless
+    Disasm, // Print a listing of a ROM
+    Asm, // Assemble Octo source into a ROM
+    Info, // Print a ROM's size, hash and database entry
+    Analyze, // Print a ROM's control-flow graph, found without running it
+    Test, // Run a ROM without a window and print the screen it ends on
+    Help,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "run" => Some(Command::Run),
+            "disasm" => Some(Command::Disasm),
+            "asm" => Some(Command::Asm),
+            "info" => Some(Command::Info),
+            "analyze" => Some(Command::Analyze),
+            "test" => Some(Command::Test),
+            "help" => Some(Command::Help),
+            _ => None,
+        }
+    }
+}
+
+// Every option: its name, what its value looks like if it takes one, and its help.
+const FLAGS: [(&str, Option<&str>, &str); 30] = [
+    ("--config", Some("FILE"), "Read options from this TOML file instead of ~/.chip8.toml"),
+    ("--rom-dir", Some("DIR"), "Where the launcher looks for ROMs [default: .]"),
+    ("--platform", Some("ID"), "Emulate this interpreter's quirks and speed, see below"),
+    ("--quirks", Some("LIST"), "Quirks to turn on or off, e.g. vblank,shift=off, see below"),
+    ("--speed", Some("N"), "Instructions executed per 60 Hz frame"),
+    ("--cycles-per-frame", Some("N"), "The same as --speed"),
+    ("--scale", Some("N"), "Initial window size as a multiple of 64x32 [default: 10]"),
+    ("--integer-scale", None, "Only scale the screen by whole multiples"),
+    ("--palette", Some("PALETTE"), "A palette name, see below, or 2 or 4 comma separated hex colours"),
+    ("--filter", Some("MODE"), "Phosphor filter: persistence or blend"),
+    ("--decay", Some("N"), "How quickly the phosphor filter fades, 0 to 1 [default: 0.6]"),
+    ("--keymap", Some("KEYS"), "16 host keys for CHIP-8 keys 0 to F [default: X123QWEASDZC4RFV]"),
+    ("--seed", Some("N"), "Seed the random number generator for repeatable runs"),
+    ("--terminal", Some("MODE"), "Draw in the terminal instead of a window: half or braille"),
+    ("--vsync", None, "Present frames in step with the monitor"),
+    ("--overlay", None, "Start with the FPS overlay shown"),
+    ("--debugger", None, "Start with the debugger window open"),
+    ("--watch", None, "Restart the ROM whenever its file changes"),
+    ("--strict", None, "Check the ROM for suspicious behaviour and report it when it stops"),
+    ("--coverage", Some("FILE"), "Count the instructions the ROM runs and write a report when it stops"),
+    ("--profile", Some("FILE"), "Count what each subroutine runs and write a profile when the ROM stops"),
+    ("--log", Some("FILTER"), "Log level, e.g. debug, or levels per target, e.g. warn,cpu=trace"),
+    ("--log-file", Some("FILE"), "Write the log to a file instead of the console"),
+    ("--headless", Some("FRAMES"), "Run this many frames without a window, for exporting"),
+    ("--y4m", Some("FILE"), "With --headless, write the video to a Y4M file"),
+    ("--wav", Some("FILE"), "With --headless, write the sound to a WAV file"),
+    ("--output", Some("FILE"), "Where disasm, asm and analyze write, -o for short"),
+    ("--dot", Some("FILE"), "With analyze, also write the control-flow graph as Graphviz DOT"),
+    ("--frames", Some("N"), "How many frames test runs for [default: 600]"),
+    ("--expect", Some("FILE"), "Make test fail unless the screen matches the one saved in FILE"),
+];
+
+pub struct Options {
+    pub command: Command,
+    pub rom_path: Option<String>, // Without one the launcher menu is shown
+    pub rom_dir: String, // Where the launcher looks for ROMs
+    pub headless_frames: Option<u32>, // Run without a window for this many frames
+    pub y4m_path: Option<String>,
+    pub wav_path: Option<String>,
+    pub seed: Option<u64>,
+    pub terminal_mode: Option<TerminalMode>, // Render in the terminal instead of an SDL window
+    pub palette: Option<Palette>, // Otherwise from the ROM database, or classic
+    pub filter: Option<FilterMode>, // Phosphor filter applied before presenting
+    pub decay: f32,
+    pub scale: u32, // Initial window size as a multiple of the framebuffer
+    pub integer_scale: bool,
+    pub cycles_per_frame: Option<u32>, // Instructions executed per 60 Hz frame, otherwise from the ROM database
+    pub platform: Option<Platform>, // Otherwise from the ROM database
+    pub quirks: QuirkOverrides, // Applied over whatever the platform or ROM sets
+    pub keymap: Keymap,
+    pub vsync: bool,
+    pub overlay: bool, // Start with the FPS/IPS overlay shown
+    pub debugger: bool, // Start with the debugger window open
+    pub watch: bool, // Restart the ROM whenever its file changes
+    pub strict: bool, // Report everything suspicious the ROM does, see strict
+    pub coverage_path: Option<String>, // Where to report which instructions ran, see coverage
+    pub profile_path: Option<String>, // Where to write the time spent per subroutine, see profiler
+    pub log_filter: Option<String>, // In RUST_LOG syntax, see logging
+    pub log_file: Option<String>,
+    pub output_path: Option<String>, // For disasm, asm and analyze
+    pub dot_path: Option<String>, // Control-flow graph analyze writes
+    pub test_frames: u32,
+    pub expect_path: Option<String>, // Screen test must end on
+}
+
+impl Options {
+
+    /*
+    * Parse the command line, after the config file. An Err holds a message
+    * for the user saying what is wrong.
+    */
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            command: Command::Run,
+            rom_path: None,
+            rom_dir: ".".to_string(),
+            headless_frames: None,
+            y4m_path: None,
+            wav_path: None,
+            seed: None,
+            terminal_mode: None,
+            palette: None,
+            filter: None,
+            decay: filter::DEFAULT_DECAY,
+            scale: display::DEFAULT_SCALE,
+            integer_scale: false,
+            cycles_per_frame: None,
+            platform: None,
+            quirks: QuirkOverrides::default(),
+            keymap: Keymap::default(),
+            vsync: false,
+            overlay: false,
+            debugger: false,
+            watch: false,
+            strict: false,
+            coverage_path: None,
+            profile_path: None,
+            log_filter: None,
+            log_file: None,
+            output_path: None,
+            dot_path: None,
+            test_frames: DEFAULT_TEST_FRAMES,
+            expect_path: None,
+        };
+
+        let config_path = match args.iter().position(|arg| arg == "--config") {
+            Some(position) => Some(PathBuf::from(args.get(position + 1).ok_or("--config needs a FILE")?)),
+            None => home_config().filter(|path| path.is_file()),
+        };
+        if let Some(path) = config_path {
+            options.apply_config(&path)?;
+        }
+
+        let mut args = args.iter().skip(1).peekable();
+        if let Some(command) = args.peek().and_then(|arg| Command::from_name(arg)) {
+            options.command = command;
+            args.next();
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => options.command = Command::Help,
+                "--config" => { args.next(); },
+                "-o" => options.set("--output", args.next().map(String::as_str))?,
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    let value = match takes_value(arg)? {
+                        true => Some(args.next().ok_or_else(|| format!("{} needs a {}", arg, value_name(arg)))?.as_str()),
+                        false => None,
+                    };
+                    options.set(arg, value)?;
+                },
+                _ if options.rom_path.is_none() => options.rom_path = Some(arg.clone()),
+                _ => return Err(format!("Unexpected argument {}, only one ROM can be given", arg)),
+            }
+        }
+
+        match options.command {
+            Command::Disasm | Command::Info | Command::Analyze | Command::Test if options.rom_path.is_none() => Err("No ROM given".to_string()),
+            Command::Asm if options.rom_path.is_none() => Err("No Octo source file given".to_string()),
+            // Exporting has no window to show the launcher in
+            Command::Run if options.headless_frames.is_some() && options.rom_path.is_none() => Err("--headless needs a ROM".to_string()),
+            _ => Ok(options),
+        }
+    }
+
+    /*
+    * Set one option from its flag and value, the value already checked to be
+    * there if the flag takes one.
+    */
+    fn set(&mut self, flag: &str, value: Option<&str>) -> Result<(), String> {
+        let text = || value.unwrap_or_default().to_string();
+        match flag {
+            "--rom-dir" => self.rom_dir = text(),
+            "--platform" => {
+                self.platform = Some(Platform::from_id(&text())
+                    .ok_or_else(|| format!("Unknown platform {}, expected one of {}", text(), Platform::ids().join(", ")))?);
+            },
+            "--quirks" => self.quirks = QuirkOverrides::parse(&text())?,
+            "--speed" | "--cycles-per-frame" => self.cycles_per_frame = Some(parse_number(flag, &text())?),
+            "--scale" => self.scale = parse_number::<u32>(flag, &text())?.max(1),
+            "--integer-scale" => self.integer_scale = true,
+            "--palette" => self.palette = Some(Palette::parse(&text())?),
+            "--filter" => {
+                self.filter = Some(FilterMode::from_name(&text())
+                    .ok_or_else(|| format!("Unknown filter {}, expected persistence or blend", text()))?);
+            },
+            "--decay" => self.decay = parse_number(flag, &text())?,
+            "--keymap" => self.keymap = Keymap::parse(&text())?,
+            "--seed" => self.seed = Some(parse_number(flag, &text())?),
+            "--terminal" => {
+                self.terminal_mode = Some(TerminalMode::from_name(&text())
+                    .ok_or_else(|| format!("Unknown terminal mode {}, expected half or braille", text()))?);
+            },
+            "--vsync" => self.vsync = true,
+            "--overlay" => self.overlay = true,
+            "--debugger" => self.debugger = true,
+            "--watch" => self.watch = true,
+            "--strict" => self.strict = true,
+            "--coverage" => self.coverage_path = Some(text()),
+            "--profile" => self.profile_path = Some(text()),
+            "--log" => self.log_filter = Some(text()),
+            "--log-file" => self.log_file = Some(text()),
+            "--headless" => self.headless_frames = Some(parse_number(flag, &text())?),
+            "--y4m" => self.y4m_path = Some(text()),
+            "--wav" => self.wav_path = Some(text()),
+            "--output" => self.output_path = Some(value.ok_or("-o needs a FILE")?.to_string()),
+            "--dot" => self.dot_path = Some(text()),
+            "--frames" => self.test_frames = parse_number(flag, &text())?,
+            "--expect" => self.expect_path = Some(text()),
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+        Ok(())
+    }
+
+    /*
+    * Set the options in a TOML config file. Keys are option names without the
+    * leading dashes, and options without a value are set with true.
+    */
+    fn apply_config(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("Cannot read config file {}: {}", path.display(), error))?;
+        let table: toml::Table = text.parse().map_err(|error| format!("Config file {} is not valid TOML: {}", path.display(), error))?;
+
+        for (key, value) in table {
+            let flag = format!("--{}", key);
+            let in_file = |error: String| format!("{}: {}", path.display(), error);
+            if flag == "--config" {
+                return Err(in_file("config files cannot include other config files".to_string()));
+            }
+            let value = match (takes_value(&flag).map_err(in_file)?, value) {
+                (false, toml::Value::Boolean(true)) => None,
+                (false, toml::Value::Boolean(false)) => continue,
+                (false, _) => return Err(in_file(format!("{} must be true or false", key))),
+                (true, toml::Value::String(text)) => Some(text),
+                (true, toml::Value::Integer(number)) => Some(number.to_string()),
+                (true, toml::Value::Float(number)) => Some(number.to_string()),
+                (true, _) => return Err(in_file(format!("{} must be a {}", key, value_name(&flag)))),
+            };
+            self.set(&flag, value.as_deref()).map_err(in_file)?;
+        }
+        Ok(())
+    }
+
+    pub fn phosphor_filter(&self) -> Option<PhosphorFilter> {
+        self.filter.map(|mode| PhosphorFilter::new(mode, self.decay))
+    }
+
+    /*
+    * What --help prints.
+    */
+    pub fn help(program: &str) -> String {
+        let mut help = format!("A CHIP-8 emulator\n\n\
+            Usage: {0} [run] [ROM] [OPTIONS]\n       \
+                   {0} disasm ROM [--output FILE]\n       \
+                   {0} asm SOURCE [--output FILE]\n       \
+                   {0} info ROM\n       \
+                   {0} analyze ROM [--output FILE] [--dot FILE]\n       \
+                   {0} test ROM [--frames N] [--expect FILE] [OPTIONS]\n\n\
+            Commands:\n  \
+              run     Play a ROM, or choose one in the launcher when none is given\n  \
+              disasm  Print a listing of a ROM\n  \
+              asm     Assemble Octo source into a ROM, written next to the source by default\n  \
+              info    Print a ROM's size, SHA1 hash and what the ROM database knows about it\n  \
+              analyze Print a ROM's blocks, subroutines and data, found without running it\n  \
+              test    Run a ROM without a window and print the screen it ends on\n\n\
+            Options:\n", program);
+
+        for (flag, value, description) in FLAGS {
+            let usage = match value {
+                Some(value) => format!("{} {}", flag, value),
+                None => flag.to_string(),
+            };
+            help.push_str(&format!("  {:<24}{}\n", usage, description));
+        }
+        help.push_str(&format!("  {:<24}{}\n\n", "-h, --help", "Print this help"));
+
+        help.push_str(&format!("Platforms: {}\n", Platform::ids().join(", ")));
+        help.push_str("Quirks: shift, memory-increment-by-x, memory-leave-i-unchanged, wrap, jump, vblank, logic\n");
+        help.push_str(&format!("Palettes: {}\n", Palette::names().join(", ")));
+        help.push_str("Log targets: cpu, display, input, timers\n\n");
+        help.push_str("Options are also read from ~/.chip8.toml, by name without the dashes,\n\
+                       e.g. palette = \"amber\" or vsync = true. The command line wins.\n");
+        help
+    }
+}
+
+fn flag(name: &str) -> Result<&'static (&'static str, Option<&'static str>, &'static str), String> {
+    FLAGS.iter().find(|(flag, _, _)| *flag == name).ok_or_else(|| format!("Unknown option {}", name))
+}
+
+fn takes_value(name: &str) -> Result<bool, String> {
+    flag(name).map(|(_, value, _)| value.is_some())
+}
+
+fn value_name(name: &str) -> &'static str {
+    flag(name).ok().and_then(|(_, value, _)| *value).unwrap_or("value")
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("{} needs a number, not {}", flag, text))
+}
+
+fn home_config() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(CONFIG_FILE))
+}
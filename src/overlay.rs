@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+// How long a notification stays on screen.
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(2);
+
+/*
+* Text drawn over the game: frame and instruction rates, the emulation profile,
+* the speed indicator and short notifications. The rates and profile are only
+* shown while the overlay is enabled, the rest always is.
+*/
+pub struct Overlay {
+    pub enabled: bool,
+    profile: String,
+    status: String,
+    notifications: Vec<(String, Instant)>,
+    sample_start: Instant,
+    sample_frames: u32,
+    sample_instructions: u64,
+    fps: f32,
+    ips: f32,
+}
+
+impl Overlay {
+    pub fn new(enabled: bool, profile: &str) -> Self {
+        Overlay {
+            enabled,
+            profile: profile.to_string(),
+            status: String::new(),
+            notifications: Vec::new(),
+            sample_start: Instant::now(),
+            sample_frames: 0,
+            sample_instructions: 0,
+            fps: 0.0,
+            ips: 0.0,
+        }
+    }
+
+    /*
+    * Count emulated frames and instructions, the rates are recalculated about
+    * once a second.
+    */
+    pub fn record(&mut self, frames: u32, instructions: u64) {
+        self.sample_frames += frames;
+        self.sample_instructions += instructions;
+
+        let elapsed = self.sample_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.sample_frames as f32 / elapsed.as_secs_f32();
+            self.ips = self.sample_instructions as f32 / elapsed.as_secs_f32();
+            self.sample_start = Instant::now();
+            self.sample_frames = 0;
+            self.sample_instructions = 0;
+        }
+    }
+
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+
+    /*
+    * Show a message for a couple of seconds, e.g. "State saved to slot 2".
+    */
+    pub fn notify(&mut self, message: &str) {
+        self.notifications.push((message.to_string(), Instant::now()));
+    }
+
+    /*
+    * The lines of text to draw, top to bottom.
+    */
+    pub fn lines(&mut self) -> Vec<String> {
+        self.notifications.retain(|(_, shown_at)| shown_at.elapsed() < NOTIFICATION_DURATION);
+
+        let mut lines = Vec::new();
+        if self.enabled {
+            lines.push(format!("FPS {:.0} IPS {:.0}", self.fps, self.ips));
+            lines.push(self.profile.clone());
+        }
+        if !self.status.is_empty() {
+            lines.push(self.status.clone());
+        }
+        lines.extend(self.notifications.iter().map(|(message, _)| message.clone()));
+        lines
+    }
+}
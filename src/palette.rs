@@ -0,0 +1,101 @@
+/*
+* A colour in 8-bit RGB.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    /*
+    * Parse a colour written as RRGGBB, with or without a leading #.
+    */
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let digits = hex.trim().trim_start_matches('#');
+        let value = u32::from_str_radix(digits, 16)
+            .ok()
+            .filter(|_| digits.len() == 6)
+            .ok_or_else(|| format!("Invalid colour '{}', expected RRGGBB", hex))?;
+
+        Ok(Rgb::new((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+}
+
+/*
+* The colours used to present the framebuffer.
+* Framebuffer values are a mask of the bitplanes lit at that pixel, so plain
+* CHIP-8 only uses the first two colours and XO-CHIP uses all four:
+*   0 - background
+*   1 - plane 1 (the only plane on CHIP-8)
+*   2 - plane 2
+*   3 - both planes
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+/*
+* Built-in palettes, selected by name.
+*/
+const PALETTES: [(&str, Palette); 6] = [
+    ("classic", Palette { colors: [Rgb::new(0x00, 0x00, 0x00), Rgb::new(0xFF, 0xFF, 0xFF), Rgb::new(0xAA, 0xAA, 0xAA), Rgb::new(0x55, 0x55, 0x55)] }),
+    ("green", Palette { colors: [Rgb::new(0x0A, 0x1A, 0x0A), Rgb::new(0x33, 0xFF, 0x33), Rgb::new(0x1A, 0x8C, 0x1A), Rgb::new(0x99, 0xFF, 0x99)] }),
+    ("amber", Palette { colors: [Rgb::new(0x1A, 0x10, 0x00), Rgb::new(0xFF, 0xB0, 0x00), Rgb::new(0x99, 0x6A, 0x00), Rgb::new(0xFF, 0xD6, 0x80)] }),
+    ("gameboy", Palette { colors: [Rgb::new(0x9B, 0xBC, 0x0F), Rgb::new(0x0F, 0x38, 0x0F), Rgb::new(0x8B, 0xAC, 0x0F), Rgb::new(0x30, 0x62, 0x30)] }),
+    ("high-contrast", Palette { colors: [Rgb::new(0x00, 0x00, 0x00), Rgb::new(0xFF, 0xFF, 0x00), Rgb::new(0x00, 0xFF, 0xFF), Rgb::new(0xFF, 0xFF, 0xFF)] }),
+    ("octo", Palette { colors: [Rgb::new(0x99, 0x66, 0x00), Rgb::new(0xFF, 0xCC, 0x00), Rgb::new(0xFF, 0x66, 0x00), Rgb::new(0x66, 0x22, 0x00)] }),
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        PALETTES[0].1
+    }
+}
+
+impl Palette {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PALETTES.iter().find(|(palette_name, _)| *palette_name == name).map(|(_, palette)| *palette)
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        PALETTES.iter().map(|(name, _)| *name).collect()
+    }
+
+    /*
+    * Parse a palette given either by name or as a comma separated list of two
+    * or four hex colours. With two colours the extra XO-CHIP planes use the
+    * foreground colour.
+    */
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(palette) = Self::from_name(spec) {
+            return Ok(palette);
+        }
+
+        let colors = spec.split(',').map(Rgb::from_hex).collect::<Result<Vec<Rgb>, String>>()?;
+        match colors.len() {
+            2 => Ok(Palette { colors: [colors[0], colors[1], colors[1], colors[1]] }),
+            4 => Ok(Palette { colors: [colors[0], colors[1], colors[2], colors[3]] }),
+            _ => Err(format!("Unknown palette '{}', expected one of {} or 2 or 4 hex colours",
+                             spec, Self::names().join(", "))),
+        }
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    /*
+    * Colour the framebuffer, producing one Rgb per pixel.
+    */
+    pub fn apply(&self, video_buffer: &[u8]) -> Vec<Rgb> {
+        video_buffer.iter().map(|pixel| self.colors[(*pixel & 0x3) as usize]).collect()
+    }
+}
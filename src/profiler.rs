@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+use crate::analysis;
+use crate::constants::*;
+
+const HOTTEST_LOOPS: usize = 10; // How many loops the report lists
+
+/*
+* Where a ROM spends its time, by subroutine. The CPU's stack pointer shows
+* when 2nnn calls and 00EE returns, and the profiler keeps the entry point of
+* each subroutine being run alongside it. Every instruction counts for the
+* subroutine running it, and for every subroutine waiting on it, and so does
+* each frame.
+*/
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    exclusive: u64, // Instructions run in the subroutine itself
+    inclusive: u64, // Instructions run in it and everything it called
+    exclusive_frames: u64, // Frames that ended in the subroutine itself
+    inclusive_frames: u64, // Frames that ended in it or something it called
+}
+
+/*
+* A jump back to an earlier instruction in the same subroutine.
+*/
+struct Loop {
+    subroutine: u16,
+    iterations: u64,
+}
+
+pub struct Profiler {
+    stack: Vec<u16>, // Entry points of the subroutines being run, main at the bottom
+    subroutines: BTreeMap<u16, Subroutine>,
+    stacks: BTreeMap<Vec<u16>, u64>, // Instructions run under each chain of calls
+    loops: BTreeMap<(u16, u16), Loop>, // By the addresses of the first and last instructions
+    counts: Vec<u64>, // Instructions run at each address
+    last: Option<(u16, bool)>, // The previous instruction, and whether it left the stack as it was
+    instructions: u64,
+    frames: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        let mut profiler = Profiler {
+            stack: Vec::new(),
+            subroutines: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            loops: BTreeMap::new(),
+            counts: vec![0; MEMORY_SIZE],
+            last: None,
+            instructions: 0,
+            frames: 0,
+        };
+        profiler.restart();
+        profiler
+    }
+}
+
+impl Profiler {
+    /*
+    * Start again from main, as the program does after a reset
+    */
+    pub fn restart(&mut self) {
+        self.stack = vec![ROM_START];
+        self.subroutines.entry(ROM_START).or_default().calls += 1;
+        self.last = None;
+    }
+
+    /*
+    * Count an instruction that has just run, with the stack pointer and
+    * program counter it left behind
+    */
+    pub fn record(&mut self, address: u16, sp: u8, pc: u16) {
+        let address = address % MEMORY_SIZE as u16;
+        let current = *self.stack.last().unwrap();
+        if let Some((last, true)) = self.last {
+            if address <= last {
+                self.loops.entry((address, last))
+                    .or_insert(Loop { subroutine: current, iterations: 0 })
+                    .iterations += 1;
+            }
+        }
+
+        self.instructions += 1;
+        self.counts[address as usize] += 1;
+        self.subroutines.entry(current).or_default().exclusive += 1;
+        for (depth, &entry) in self.stack.iter().enumerate() {
+            if !self.stack[..depth].contains(&entry) {
+                self.subroutines.entry(entry).or_default().inclusive += 1;
+            }
+        }
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            },
+        }
+
+        let depth = self.stack.len();
+        self.stack.truncate(sp as usize + 1);
+        if self.stack.len() < sp as usize + 1 {
+            self.stack.push(pc);
+            self.subroutines.entry(pc).or_default().calls += 1;
+        }
+        self.last = Some((address, self.stack.len() == depth));
+    }
+
+    /*
+    * Count a frame for whatever is running as it ends
+    */
+    pub fn frame(&mut self) {
+        self.frames += 1;
+        let current = *self.stack.last().unwrap();
+        self.subroutines.entry(current).or_default().exclusive_frames += 1;
+        for (depth, &entry) in self.stack.iter().enumerate() {
+            if !self.stack[..depth].contains(&entry) {
+                self.subroutines.entry(entry).or_default().inclusive_frames += 1;
+            }
+        }
+    }
+
+    /*
+    * The profile as a table of subroutines, the busiest first, and the
+    * loops that ran the most instructions. A loop's instructions are the ones
+    * its subroutine ran between its first and last instruction, not counting
+    * the subroutines it calls.
+    */
+    pub fn report(&self) -> String {
+        let mut report = format!("; {} instructions over {} frames\n\n", self.instructions, self.frames);
+        report.push_str(&format!("{:<12}{:>10}{:>14}{:>8}{:>14}{:>8}{:>10}{:>10}\n",
+                                 "Subroutine", "Calls", "Self", "%", "Total", "%", "Frames", "Total"));
+
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        for (&entry, subroutine) in subroutines {
+            report.push_str(&format!("{:<12}{:>10}{:>14}{:>8}{:>14}{:>8}{:>10}{:>10}\n",
+                                     analysis::label(entry), subroutine.calls,
+                                     subroutine.exclusive, self.percent(subroutine.exclusive),
+                                     subroutine.inclusive, self.percent(subroutine.inclusive),
+                                     subroutine.exclusive_frames, subroutine.inclusive_frames));
+        }
+
+        let mut loops: Vec<(u16, u16, &Loop, u64)> = self.loops.iter()
+            .map(|(&(first, last), hot_loop)| {
+                let instructions = self.counts[first as usize..=last as usize].iter().sum();
+                (first, last, hot_loop, instructions)
+            })
+            .collect();
+        loops.sort_by(|a, b| b.3.cmp(&a.3).then(a.0.cmp(&b.0)));
+        if !loops.is_empty() {
+            report.push_str("\nHottest loops:\n");
+        }
+        for (first, last, hot_loop, instructions) in loops.into_iter().take(HOTTEST_LOOPS) {
+            report.push_str(&format!("  {:03X}-{:03X} in {}: {} iterations, {} instructions ({}%)\n",
+                                     first, last, analysis::label(hot_loop.subroutine),
+                                     hot_loop.iterations, instructions, self.percent(instructions)));
+        }
+        report
+    }
+
+    /*
+    * The instructions run under each chain of calls in the folded-stack
+    * format flamegraph.pl and inferno read, e.g. "main;sub_2A0 1234".
+    */
+    pub fn folded(&self) -> String {
+        self.stacks.iter()
+            .map(|(stack, count)| {
+                let names: Vec<String> = stack.iter().map(|&entry| analysis::label(entry)).collect();
+                format!("{} {}\n", names.join(";"), count)
+            })
+            .collect()
+    }
+
+    fn percent(&self, instructions: u64) -> u64 {
+        (instructions * 100).checked_div(self.instructions).unwrap_or(0)
+    }
+}
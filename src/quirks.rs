@@ -0,0 +1,141 @@
+use serde::Deserialize;
+
+/*
+* Behaviours that differ between CHIP-8 interpreters, named as in the
+* chip-8-database. Games written for one interpreter often break on another.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    pub shift: bool, // 8xy6 and 8xyE shift Vx in place, ignoring Vy
+    pub memory_increment_by_x: bool, // Fx55 and Fx65 leave I increased by x
+    pub memory_leave_i_unchanged: bool, // Fx55 and Fx65 leave I as it was, otherwise it is increased by x + 1
+    pub wrap: bool, // Sprites wrap around the screen edges instead of being clipped
+    pub jump: bool, // Bnnn jumps to nnn + Vx, x being the top nibble of nnn, instead of nnn + V0
+    pub vblank: bool, // Dxyn waits for the start of the next frame
+    pub logic: bool, // 8xy1, 8xy2 and 8xy3 reset VF to 0
+}
+
+impl Default for Quirks {
+    /*
+    * The behaviour the emulator has always had, which suits most modern ROMs.
+    */
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: true,
+            jump: false,
+            vblank: false,
+            logic: false,
+        }
+    }
+}
+
+/*
+* Individual quirks a database entry sets for one ROM, on top of its platform's.
+*/
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuirkOverrides {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    /*
+    * A comma separated list of quirks, each turned on by its name or set with
+    * NAME=on|off, e.g. "vblank,shift=off". Names are matched ignoring case,
+    * dashes and underscores, so "memory-increment-by-x" works too.
+    */
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut overrides = QuirkOverrides::default();
+        for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (name, value) = item.split_once('=').unwrap_or((item, "on"));
+            let value = match value.trim().to_ascii_lowercase().as_str() {
+                "on" | "true" | "1" => true,
+                "off" | "false" | "0" => false,
+                _ => return Err(format!("Quirk {} must be on or off, not {}", name, value)),
+            };
+            let field = match normalise(name).as_str() {
+                "shift" => &mut overrides.shift,
+                "memoryincrementbyx" => &mut overrides.memory_increment_by_x,
+                "memoryleaveiunchanged" => &mut overrides.memory_leave_i_unchanged,
+                "wrap" => &mut overrides.wrap,
+                "jump" => &mut overrides.jump,
+                "vblank" => &mut overrides.vblank,
+                "logic" => &mut overrides.logic,
+                _ => return Err(format!("Unknown quirk {}, expected one of {}", name, QUIRK_NAMES.join(", "))),
+            };
+            *field = Some(value);
+        }
+        Ok(overrides)
+    }
+}
+
+const QUIRK_NAMES: [&str; 7] = ["shift", "memory-increment-by-x", "memory-leave-i-unchanged", "wrap", "jump", "vblank", "logic"];
+
+fn normalise(name: &str) -> String {
+    name.trim().chars().filter(|c| *c != '-' && *c != '_').map(|c| c.to_ascii_lowercase()).collect()
+}
+
+impl Quirks {
+    pub fn with_overrides(mut self, overrides: &QuirkOverrides) -> Self {
+        self.shift = overrides.shift.unwrap_or(self.shift);
+        self.memory_increment_by_x = overrides.memory_increment_by_x.unwrap_or(self.memory_increment_by_x);
+        self.memory_leave_i_unchanged = overrides.memory_leave_i_unchanged.unwrap_or(self.memory_leave_i_unchanged);
+        self.wrap = overrides.wrap.unwrap_or(self.wrap);
+        self.jump = overrides.jump.unwrap_or(self.jump);
+        self.vblank = overrides.vblank.unwrap_or(self.vblank);
+        self.logic = overrides.logic.unwrap_or(self.logic);
+        self
+    }
+}
+
+/*
+* An interpreter a ROM was written for, by its chip-8-database id.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Platform {
+    pub id: &'static str,
+    pub quirks: Quirks,
+    pub tick_rate: u32, // Instructions per 60 Hz frame
+}
+
+const fn platform(id: &'static str, quirks: [bool; 7], tick_rate: u32) -> Platform {
+    let [shift, memory_increment_by_x, memory_leave_i_unchanged, wrap, jump, vblank, logic] = quirks;
+    Platform {
+        id,
+        quirks: Quirks { shift, memory_increment_by_x, memory_leave_i_unchanged, wrap, jump, vblank, logic },
+        tick_rate,
+    }
+}
+
+// Quirks in the order shift, memory_increment_by_x, memory_leave_i_unchanged,
+// wrap, jump, vblank, logic.
+pub const PLATFORMS: [Platform; 9] = [
+    platform("originalChip8", [false, false, false, false, false, true, true], 15),
+    platform("hybridVIP", [false, false, false, false, false, true, true], 15),
+    platform("modernChip8", [false, false, false, false, false, false, false], 12),
+    platform("chip8x", [false, false, false, false, false, true, true], 15),
+    platform("chip48", [true, true, false, false, true, false, false], 30),
+    platform("superchip1", [true, false, true, false, true, false, false], 30),
+    platform("superchip", [true, false, true, false, true, false, false], 30),
+    platform("megachip8", [true, false, true, false, true, false, false], 1000),
+    platform("xochip", [false, false, false, true, false, false, false], 100),
+];
+
+impl Platform {
+    pub fn from_id(id: &str) -> Option<Platform> {
+        PLATFORMS.iter().find(|platform| platform.id.eq_ignore_ascii_case(id)).copied()
+    }
+
+    pub fn ids() -> Vec<&'static str> {
+        PLATFORMS.iter().map(|platform| platform.id).collect()
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::{Duration, Instant};
+use log::debug;
+use crate::logging;
+
+// When further behind than this many frames, e.g. after the window was dragged
+// or the process stopped, the missed frames are dropped instead of replayed.
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+// Sleeping is only accurate to around a millisecond, the rest is spun.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+/*
+* Paces emulation to a fixed frame rate using the monotonic clock, so speed no
+* longer depends on how long the OS takes to wake a sleeping thread.
+*/
+pub struct Scheduler {
+    base_frame_duration: Duration,
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl Scheduler {
+    pub fn new(frame_rate: u32) -> Self {
+        let frame_duration = Duration::from_secs(1) / frame_rate;
+        Scheduler {
+            base_frame_duration: frame_duration,
+            frame_duration,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /*
+    * Run at a percentage of full speed, e.g. 50 for half speed.
+    */
+    pub fn set_speed(&mut self, percent: u32) {
+        self.frame_duration = self.base_frame_duration * 100 / percent.max(1);
+        self.reset();
+    }
+
+    /*
+    * Start pacing again from now, forgetting any frames that are owed,
+    * e.g. after being paused.
+    */
+    pub fn reset(&mut self) {
+        self.next_frame = Instant::now();
+    }
+
+    /*
+    * How long one frame lasts at the current speed.
+    */
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    /*
+    * Block until the next frame is due and return how many frames should be
+    * emulated before presenting. This is 1 when keeping up, more when catching
+    * up after falling behind.
+    */
+    pub fn wait(&mut self) -> u32 {
+        let now = Instant::now();
+
+        if now < self.next_frame {
+            let remaining = self.next_frame - now;
+            if remaining > SPIN_THRESHOLD {
+                std::thread::sleep(remaining - SPIN_THRESHOLD / 2);
+            }
+            while Instant::now() < self.next_frame {
+                std::thread::yield_now();
+            }
+            self.next_frame += self.frame_duration;
+            return 1;
+        }
+
+        let behind = ((now - self.next_frame).as_nanos() / self.frame_duration.as_nanos()) as u32 + 1;
+        if behind > MAX_CATCH_UP_FRAMES {
+            // Drop the frames we cannot make up and start pacing again from now
+            debug!(target: logging::TIMERS, "{} frames behind, dropping them", behind);
+            self.next_frame = now + self.frame_duration;
+            return 1;
+        }
+
+        if behind > 1 {
+            debug!(target: logging::TIMERS, "{} frames behind, catching up", behind - 1);
+        }
+        self.next_frame += self.frame_duration * behind;
+        behind
+    }
+}
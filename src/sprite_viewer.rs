@@ -0,0 +1,206 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use crate::constants::*;
+use crate::cpu::Cpu;
+use crate::display::{self, GLYPH_HEIGHT};
+use crate::frontend::SpriteViewerAction;
+
+const WINDOW_WIDTH: u32 = 760;
+const WINDOW_HEIGHT: u32 = 520;
+const TEXT_SCALE: u32 = 2;
+const LINE_HEIGHT: i32 = ((GLYPH_HEIGHT + 2) * TEXT_SCALE) as i32;
+const MARGIN: i32 = 8;
+
+// The sprite grid fills the left of the window, the list of drawn sprites the right.
+const GRID_WIDTH: i32 = 560;
+const GRID_TOP: i32 = MARGIN + 2 * LINE_HEIGHT;
+const LIST_LEFT: i32 = GRID_WIDTH + 2 * MARGIN;
+
+const PIXEL_SIZE: i32 = 4; // Window pixels per sprite pixel
+const LARGE_PIXEL_SIZE: i32 = 3;
+const CELL_PADDING: i32 = 10;
+
+const MAX_HEIGHT: usize = 15; // Dxyn can draw at most 15 rows
+const LARGE_SPRITE_BYTES: usize = 32; // 16x16, two bytes per row
+
+const BACKGROUND: Color = Color::RGB(0x1E, 0x1E, 0x1E);
+const HEADING: Color = Color::RGB(0xFF, 0xCC, 0x00);
+const TEXT: Color = Color::RGB(0xDD, 0xDD, 0xDD);
+const DIM: Color = Color::RGB(0x70, 0x70, 0x70);
+const DRAWN: Color = Color::RGB(0x33, 0xFF, 0x33); // Bytes Dxyn has drawn from
+const INDEX: Color = Color::RGB(0x33, 0xCC, 0xFF); // The sprite I points into
+const CELL: Color = Color::RGB(0x2A, 0x2A, 0x2A);
+
+/*
+* A window that interprets memory as a grid of sprites, 8 pixels wide and 1-15
+* rows tall as drawn by Dxyn, or 16x16 as drawn by the SCHIP Dxy0. Rows that
+* Dxyn has drawn from this session are shown in green, and the sprites it drew
+* are listed alongside.
+*/
+pub struct SpriteViewer {
+    canvas: Canvas<Window>,
+    address: usize, // First sprite in the grid
+    height: usize,
+    large: bool,
+    jump_to_index: bool, // Move to I on the next draw, which has the CPU
+}
+
+impl SpriteViewer {
+    pub fn new(video_subsystem: &sdl2::VideoSubsystem) -> Self {
+        let window = video_subsystem.window("Chip-8 Sprites", WINDOW_WIDTH, WINDOW_HEIGHT)
+            .position_centered()
+            .build()
+            .unwrap();
+
+        SpriteViewer {
+            canvas: window.into_canvas().build().unwrap(),
+            address: FONTSET_START_ADDRESS as usize,
+            height: 5,
+            large: false,
+            jump_to_index: false,
+        }
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    fn sprite_bytes(&self) -> usize {
+        if self.large { LARGE_SPRITE_BYTES } else { self.height }
+    }
+
+    fn pixel_size(&self) -> i32 {
+        if self.large { LARGE_PIXEL_SIZE } else { PIXEL_SIZE }
+    }
+
+    fn cell_size(&self) -> (i32, i32) {
+        let (width, height) = if self.large { (16, 16) } else { (8, self.height as i32) };
+        (width * self.pixel_size() + CELL_PADDING,
+         height * self.pixel_size() + LINE_HEIGHT + CELL_PADDING)
+    }
+
+    fn grid_size(&self) -> (usize, usize) {
+        let (cell_width, cell_height) = self.cell_size();
+        (((GRID_WIDTH - MARGIN) / cell_width) as usize,
+         ((WINDOW_HEIGHT as i32 - GRID_TOP - MARGIN) / cell_height).max(1) as usize)
+    }
+
+    pub fn handle(&mut self, action: SpriteViewerAction) {
+        match action {
+            SpriteViewerAction::Scroll(rows) => {
+                let row_bytes = (self.grid_size().0 * self.sprite_bytes()) as i32;
+                self.address = (self.address as i32 + rows * row_bytes).clamp(0, MEMORY_SIZE as i32 - 1) as usize;
+            },
+            SpriteViewerAction::Height(change) => {
+                self.large = false;
+                self.height = (self.height as i32 + change).clamp(1, MAX_HEIGHT as i32) as usize;
+            },
+            SpriteViewerAction::ToggleLarge => self.large = !self.large,
+            SpriteViewerAction::JumpToIndex => self.jump_to_index = true,
+            SpriteViewerAction::JumpToFont => {
+                self.address = FONTSET_START_ADDRESS as usize;
+                self.height = 5;
+                self.large = false;
+            },
+        }
+    }
+
+    fn text(&mut self, text: &str, x: i32, y: i32, color: Color) {
+        display::draw_text(&mut self.canvas, text, x, y, TEXT_SCALE, color);
+    }
+
+    pub fn draw(&mut self, cpu: &Cpu) {
+        if self.jump_to_index {
+            self.address = cpu.index() as usize % MEMORY_SIZE;
+            self.jump_to_index = false;
+        }
+
+        // Every byte any Dxyn has read, sprites can overlap
+        let mut drawn = [false; MEMORY_SIZE];
+        for (&address, &height) in cpu.sprites_drawn() {
+            for offset in 0..height as usize {
+                drawn[(address as usize + offset) % MEMORY_SIZE] = true;
+            }
+        }
+
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+
+        let size = if self.large { "16X16".to_string() } else { format!("8X{}", self.height) };
+        self.text(&format!("SPRITES {} FROM {:03X}  I {:03X}", size, self.address, cpu.index()), MARGIN, MARGIN, HEADING);
+
+        self.draw_grid(cpu, &drawn);
+        self.draw_list(cpu);
+
+        self.canvas.present();
+    }
+
+    fn draw_grid(&mut self, cpu: &Cpu, drawn: &[bool]) {
+        let (columns, rows) = self.grid_size();
+        let (cell_width, cell_height) = self.cell_size();
+        let pixel_size = self.pixel_size();
+        let sprite_bytes = self.sprite_bytes();
+        let row_bytes = if self.large { 2 } else { 1 };
+        let index = cpu.index() as usize;
+
+        for sprite in 0..columns * rows {
+            let address = self.address + sprite * sprite_bytes;
+            if address >= MEMORY_SIZE {
+                break;
+            }
+            let x = MARGIN + (sprite % columns) as i32 * cell_width;
+            let y = GRID_TOP + (sprite / columns) as i32 * cell_height;
+
+            let label_color = if (address..address + sprite_bytes).contains(&index) {
+                INDEX
+            } else if cpu.sprites_drawn().contains_key(&(address as u16)) {
+                DRAWN
+            } else {
+                DIM
+            };
+            self.text(&format!("{:03X}", address), x, y, label_color);
+
+            let top = y + LINE_HEIGHT;
+            self.canvas.set_draw_color(CELL);
+            self.canvas.fill_rect(Rect::new(x,
+                                            top,
+                                            (cell_width - CELL_PADDING) as u32,
+                                            (cell_height - LINE_HEIGHT - CELL_PADDING) as u32)).unwrap();
+
+            for byte in 0..sprite_bytes.min(MEMORY_SIZE - address) {
+                let byte_address = address + byte;
+                let value = cpu.memory()[byte_address];
+                let row = (byte / row_bytes) as i32;
+                let left = x + (byte % row_bytes) as i32 * 8 * pixel_size;
+
+                self.canvas.set_draw_color(if drawn[byte_address] { DRAWN } else { TEXT });
+                for bit in 0..8 {
+                    if value & (0x80 >> bit) != 0 {
+                        self.canvas.fill_rect(Rect::new(left + bit * pixel_size,
+                                                        top + row * pixel_size,
+                                                        pixel_size as u32,
+                                                        pixel_size as u32)).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_list(&mut self, cpu: &Cpu) {
+        self.text("DRAWN FROM", LIST_LEFT, GRID_TOP, HEADING);
+
+        let rows = ((WINDOW_HEIGHT as i32 - GRID_TOP - MARGIN) / LINE_HEIGHT - 1) as usize;
+        let sprites = cpu.sprites_drawn();
+        let shown = if sprites.len() > rows { rows - 1 } else { rows };
+        for (line, (address, height)) in sprites.iter().take(shown).enumerate() {
+            let y = GRID_TOP + (line as i32 + 1) * LINE_HEIGHT;
+            self.text(&format!("{:03X} 8X{}", address, height), LIST_LEFT, y, TEXT);
+        }
+        if sprites.len() > shown {
+            let y = GRID_TOP + rows as i32 * LINE_HEIGHT;
+            self.text(&format!("+{} MORE", sprites.len() - shown), LIST_LEFT, y, DIM);
+        }
+    }
+}
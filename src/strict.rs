@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use log::{info, warn, Level};
+use crate::logging;
+
+/*
+* Things a ROM does that work by accident, or only on some interpreters, or
+* that are worth knowing about such as self-modifying code. The CPU logs each
+* of them once per instruction, and with --strict also counts every time they
+* happen for the report.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Finding {
+    InvalidInstruction, // An opcode that is not a CHIP-8 instruction
+    MemoryPastEnd, // Fx33, Fx55 or Fx65 reaching past 0xFFF
+    SpritePastEnd, // Dxyn reading sprite rows past 0xFFF
+    FontDigit, // Fx29 with Vx > 0xF
+    KeyOutOfRange, // Ex9E or ExA1 with Vx > 0xF
+    ExecutedFont, // Strict only: the PC is in the font
+    ExecutedUnwritten, // Strict only: the PC is outside the ROM in memory the program never wrote
+    MisalignedPc, // Strict only: the PC is at an odd address
+    HaltLoop, // Strict only: a jump to itself, which stops the program
+    SelfModifyingCode, // A write to memory that has been executed
+    StackOverflow, // 2nnn with every stack level in use
+    StackUnderflow, // 00EE with nothing on the stack
+}
+
+impl Finding {
+    /*
+    * How loudly to log it. Halting and patching code are often done on
+    * purpose, so they are only worth a note.
+    */
+    pub fn level(&self) -> Level {
+        match self {
+            Finding::HaltLoop | Finding::SelfModifyingCode => Level::Info,
+            _ => Level::Warn,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Finding::InvalidInstruction => "Executed an opcode that is not an instruction",
+            Finding::MemoryPastEnd => "Accessed memory past 0xFFF",
+            Finding::SpritePastEnd => "Drew a sprite reading past 0xFFF",
+            Finding::FontDigit => "Pointed I at the font character for a value above 0xF",
+            Finding::KeyOutOfRange => "Checked a key above 0xF",
+            Finding::ExecutedFont => "Executed the font",
+            Finding::ExecutedUnwritten => "Executed memory the program never wrote",
+            Finding::MisalignedPc => "Executed an instruction at an odd address",
+            Finding::HaltLoop => "Halted with a jump to itself",
+            Finding::SelfModifyingCode => "Wrote over code that had already run",
+            Finding::StackOverflow => "Called a subroutine with the stack full",
+            Finding::StackUnderflow => "Returned from a subroutine with the stack empty",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/*
+* Where and how often one kind of finding happened at one instruction.
+*/
+struct Occurrence {
+    count: u64,
+    first_cycle: u64,
+    detail: String, // Said about the first time
+}
+
+/*
+* Everything strict mode found while a ROM ran.
+*/
+#[derive(Default)]
+pub struct Report {
+    occurrences: BTreeMap<(Finding, u16), Occurrence>, // By finding, then instruction address
+}
+
+impl Report {
+    /*
+    * Count a finding at an instruction, keeping the detail of the first one
+    */
+    pub fn record(&mut self, finding: Finding, address: u16, cycle: u64, detail: &str) {
+        self.occurrences.entry((finding, address))
+            .or_insert_with(|| Occurrence { count: 0, first_cycle: cycle, detail: detail.to_string() })
+            .count += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.occurrences.is_empty()
+    }
+
+    /*
+    * The report as text, grouped by finding with each instruction that caused
+    * it, how often and when it first happened.
+    */
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "Strict mode found nothing suspicious\n".to_string();
+        }
+
+        let mut summary = format!("Strict mode found {} suspicious instructions:\n", self.occurrences.len());
+        let mut current = None;
+        for ((finding, address), occurrence) in &self.occurrences {
+            if current != Some(finding) {
+                summary.push_str(&format!("  {}\n", finding));
+                current = Some(finding);
+            }
+            summary.push_str(&format!("    {:03X}  {}x, first at instruction {}: {}\n",
+                                      address, occurrence.count, occurrence.first_cycle, occurrence.detail));
+        }
+        summary
+    }
+
+    /*
+    * Log the summary, as warnings if anything was found so it shows at the
+    * usual levels
+    */
+    pub fn log(&self) {
+        for line in self.summary().lines() {
+            if self.is_empty() {
+                info!(target: logging::CPU, "{}", line);
+            } else {
+                warn!(target: logging::CPU, "{}", line);
+            }
+        }
+    }
+}
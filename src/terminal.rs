@@ -0,0 +1,308 @@
+use std::io::{stdout, Stdout, Write};
+use std::time::{Duration, Instant};
+use crossterm::{cursor, event, execute, queue, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crate::constants::KEY_COUNT;
+use crate::frontend::{GameKeys, InputEvent, InputSource, Keymap, Renderer};
+use crate::palette::Rgb;
+
+// Terminals never report key releases, so a key is released once this long has
+// passed without the terminal repeating it.
+const KEY_HOLD: Duration = Duration::from_millis(150);
+
+/*
+* How framebuffer pixels are packed into terminal character cells.
+*/
+#[derive(Clone, Copy, PartialEq)]
+pub enum TerminalMode {
+    HalfBlock, // 1x2 pixels per cell using ▀ ▄ █
+    Braille,   // 2x4 pixels per cell using U+2800..U+28FF
+}
+
+impl TerminalMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "half" => Some(TerminalMode::HalfBlock),
+            "braille" => Some(TerminalMode::Braille),
+            _ => None,
+        }
+    }
+}
+
+/*
+* Renders the framebuffer in a terminal with Unicode characters. Needs no X
+* server, so it works over SSH and inside containers. Raw mode is held for as
+* long as this lives, which TerminalInput relies on.
+*/
+pub struct Terminal {
+    stdout: Stdout,
+    mode: TerminalMode,
+    width: usize,
+    height: usize,
+    background: Rgb,
+    overlay: Vec<String>,
+    overlay_rows: usize, // Rows the last overlay used, so stale lines can be cleared
+}
+
+/*
+* Bits of a braille character for each dot, indexed by [y][x] within the cell.
+*/
+const BRAILLE_DOTS: [[u32; 2]; 4] = [
+    [0x01, 0x08],
+    [0x02, 0x10],
+    [0x04, 0x20],
+    [0x40, 0x80],
+];
+
+impl Terminal {
+    pub fn new(mode: TerminalMode, background: Rgb) -> Self {
+        let mut stdout = stdout();
+
+        terminal::enable_raw_mode().unwrap();
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All)).unwrap();
+
+        Terminal {
+            stdout,
+            mode,
+            width: 0,
+            height: 0,
+            background,
+            overlay: Vec::new(),
+            overlay_rows: 0,
+        }
+    }
+
+    fn pixel(&self, frame: &[Rgb], x: usize, y: usize) -> Rgb {
+        if x < self.width && y < self.height { frame[y * self.width + x] } else { self.background }
+    }
+
+    /*
+    * Each cell is an upper half-block, coloured with the top pixel in front
+    * and the bottom pixel behind.
+    */
+    fn render_half_block(&self, frame: &[Rgb]) -> Vec<Vec<Cell>> {
+        (0..self.height).step_by(2).map(|y| {
+            (0..self.width).map(|x| {
+                Cell { symbol: '▀', foreground: self.pixel(frame, x, y), background: self.pixel(frame, x, y + 1) }
+            }).collect()
+        }).collect()
+    }
+
+    /*
+    * Each cell lights a dot for every pixel that is not background, in the
+    * average colour of those pixels.
+    */
+    fn render_braille(&self, frame: &[Rgb]) -> Vec<Vec<Cell>> {
+        (0..self.height).step_by(4).map(|y| {
+            (0..self.width).step_by(2).map(|x| {
+                let mut bits = 0;
+                let mut lit = Vec::new();
+                for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                    for (dx, dot) in row.iter().enumerate() {
+                        let pixel = self.pixel(frame, x + dx, y + dy);
+                        if pixel != self.background {
+                            bits |= dot;
+                            lit.push(pixel);
+                        }
+                    }
+                }
+                Cell { symbol: char::from_u32(0x2800 + bits).unwrap(), foreground: average(&lit), background: self.background }
+            }).collect()
+        }).collect()
+    }
+}
+
+/*
+* A character cell and its colours.
+*/
+struct Cell {
+    symbol: char,
+    foreground: Rgb,
+    background: Rgb,
+}
+
+fn average(colors: &[Rgb]) -> Rgb {
+    if colors.is_empty() {
+        return Rgb::new(0, 0, 0);
+    }
+    let count = colors.len() as u32;
+    let sum = |channel: fn(&Rgb) -> u8| (colors.iter().map(|color| channel(color) as u32).sum::<u32>() / count) as u8;
+    Rgb::new(sum(|color| color.r), sum(|color| color.g), sum(|color| color.b))
+}
+
+fn terminal_color(color: Rgb) -> Color {
+    Color::Rgb { r: color.r, g: color.g, b: color.b }
+}
+
+impl Renderer for Terminal {
+    fn set_background(&mut self, background: Rgb) {
+        self.background = background;
+    }
+
+    fn redraw(&mut self, frame: &[Rgb], width: usize, height: usize) {
+        if (width, height) != (self.width, self.height) {
+            queue!(self.stdout, terminal::Clear(terminal::ClearType::All)).unwrap();
+            self.width = width;
+            self.height = height;
+        }
+
+        let lines = match self.mode {
+            TerminalMode::HalfBlock => self.render_half_block(frame),
+            TerminalMode::Braille => self.render_braille(frame),
+        };
+
+        for (row, line) in lines.iter().enumerate() {
+            queue!(self.stdout, cursor::MoveTo(0, row as u16)).unwrap();
+
+            // Only send colour changes, a full frame of escapes is slow over SSH
+            let mut current: Option<(Rgb, Rgb)> = None;
+            for cell in line {
+                if current != Some((cell.foreground, cell.background)) {
+                    queue!(self.stdout,
+                           SetForegroundColor(terminal_color(cell.foreground)),
+                           SetBackgroundColor(terminal_color(cell.background))).unwrap();
+                    current = Some((cell.foreground, cell.background));
+                }
+                queue!(self.stdout, Print(cell.symbol)).unwrap();
+            }
+        }
+        // The overlay goes below the frame rather than covering it
+        queue!(self.stdout, ResetColor).unwrap();
+        for row in 0..self.overlay.len().max(self.overlay_rows) {
+            queue!(self.stdout,
+                   cursor::MoveTo(0, (lines.len() + row) as u16),
+                   terminal::Clear(terminal::ClearType::CurrentLine),
+                   Print(self.overlay.get(row).map_or("", |line| line.as_str()))).unwrap();
+        }
+        self.overlay_rows = self.overlay.len();
+        self.stdout.flush().unwrap();
+    }
+
+    fn show_overlay(&mut self, lines: &[String]) {
+        self.overlay = lines.to_vec();
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Give the user their terminal back even if the emulator panicked
+        let _ = execute!(self.stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/*
+* Keypresses read from the terminal in raw mode.
+*/
+pub struct TerminalInput {
+    pressed_at: [Option<Instant>; KEY_COUNT],
+    fast_forward_at: Option<Instant>,
+    keymap: Keymap,
+    game_keys: GameKeys,
+}
+
+impl TerminalInput {
+    pub fn new(keymap: Keymap) -> Self {
+        TerminalInput {
+            pressed_at: [None; KEY_COUNT],
+            fast_forward_at: None,
+            keymap,
+            game_keys: GameKeys::default(),
+        }
+    }
+
+    /*
+    * The CHIP-8 key the current game binds to an arrow key or space, if any.
+    * Terminals do not report shift on its own, so there is no b button.
+    */
+    fn game_key(&self, code: KeyCode) -> Option<usize> {
+        match code {
+            KeyCode::Up => self.game_keys.up,
+            KeyCode::Down => self.game_keys.down,
+            KeyCode::Left => self.game_keys.left,
+            KeyCode::Right => self.game_keys.right,
+            KeyCode::Char(' ') => self.game_keys.a,
+            _ => None,
+        }
+    }
+
+    fn press(&mut self, chip8_key: usize, events: &mut Vec<InputEvent>) {
+        if self.pressed_at[chip8_key].is_none() {
+            events.push(InputEvent::KeyDown(chip8_key));
+        }
+        self.pressed_at[chip8_key] = Some(Instant::now());
+    }
+
+    /*
+    * Emulator controls bound to keys outside the CHIP-8 keypad.
+    */
+    fn hotkey(c: char) -> Option<InputEvent> {
+        match c.to_ascii_uppercase() {
+            'P' => Some(InputEvent::TogglePause),
+            'N' => Some(InputEvent::StepFrame),
+            'M' => Some(InputEvent::StepInstruction),
+            'L' => Some(InputEvent::CycleSlowMotion),
+            'O' => Some(InputEvent::ToggleOverlay),
+            _ => None,
+        }
+    }
+}
+
+impl InputSource for TerminalInput {
+
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                if let Some(chip8_key) = self.game_key(key.code) {
+                    self.press(chip8_key, &mut events);
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Esc => events.push(InputEvent::Quit),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => events.push(InputEvent::Quit),
+                    KeyCode::Tab => {
+                        if self.fast_forward_at.is_none() {
+                            events.push(InputEvent::FastForward(true));
+                        }
+                        self.fast_forward_at = Some(Instant::now());
+                    },
+                    KeyCode::Up => events.push(InputEvent::Navigate(-1)),
+                    KeyCode::Down => events.push(InputEvent::Navigate(1)),
+                    KeyCode::Enter => events.push(InputEvent::Select),
+                    KeyCode::F(2) => events.push(InputEvent::OpenLauncher),
+                    KeyCode::F(5) => events.push(InputEvent::Reset(true)),
+                    KeyCode::F(6) => events.push(InputEvent::Reset(false)),
+                    KeyCode::Char(c) => match self.keymap.key_for_char(c) {
+                        Some(chip8_key) => self.press(chip8_key, &mut events),
+                        None => events.extend(Self::hotkey(c)),
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        for (chip8_key, pressed_at) in self.pressed_at.iter_mut().enumerate() {
+            if pressed_at.is_some_and(|time| time.elapsed() >= KEY_HOLD) {
+                *pressed_at = None;
+                events.push(InputEvent::KeyUp(chip8_key));
+            }
+        }
+        if self.fast_forward_at.is_some_and(|time| time.elapsed() >= KEY_HOLD) {
+            self.fast_forward_at = None;
+            events.push(InputEvent::FastForward(false));
+        }
+
+        events
+    }
+
+    fn set_game_keys(&mut self, keys: &GameKeys) {
+        self.game_keys = *keys;
+    }
+
+}
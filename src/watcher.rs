@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the file's modification time is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/*
+* Notices when a file is modified by polling its modification time. A change is
+* only reported once the time has stayed the same for a whole poll, so a file
+* that is still being written is not read half way through.
+*/
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>, // The version last reported or started with
+    pending: Option<SystemTime>, // A newer version, waiting to settle
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = Self::modified_time(&path);
+        FileWatcher {
+            path,
+            modified,
+            pending: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    /*
+    * Whether the file has changed since the last time this returned true.
+    * Cheap to call every frame, the file system is only asked every poll interval.
+    */
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        // Missing, e.g. while an editor replaces it, counts as unchanged
+        let modified = match Self::modified_time(&self.path) {
+            Some(modified) => modified,
+            None => return false,
+        };
+        if Some(modified) == self.modified {
+            self.pending = None;
+            return false;
+        }
+        if Some(modified) == self.pending {
+            self.modified = Some(modified);
+            self.pending = None;
+            return true;
+        }
+        self.pending = Some(modified);
+        false
+    }
+}